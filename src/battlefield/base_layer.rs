@@ -1,5 +1,5 @@
 use orbtk::prelude::*;
-use crate::battlefield::BattlefieldEvent;
+use crate::battlefield::{BattlefieldEvent,BattlefieldEvents,PhysicalCollisionGroups};
 
 enum BaseLayerAction
 {
@@ -49,17 +49,18 @@ impl State for BaseLayerState {
     }
 }
 
-type BattlefieldEvents = Vec<BattlefieldEvent>;
-
 widget!(
     /**
     Base layer is supposed to be a base layer where entity are added.
     The idea is that there could be multiple base_layer that store (and display) specific kind of object, so that hiding the layer will hide all the object of that kind.
     For example is possible to use a base layer for display obstacles and a base layer for display characters.
+    The collision_groups of the layer are the defaults of the entities added to it,
+    so that, for example, a layer of decorations can be made non colliding.
     */
     BaseLayer<BaseLayerState>
     {
-        battlefield_events: BattlefieldEvents
+        battlefield_events: BattlefieldEvents,
+        collision_groups: PhysicalCollisionGroups
     }
 );
 
@@ -1,14 +1,38 @@
 use orbtk::prelude::*;
 pub use ncollide2d::math::Isometry;
-use super::{PhysicWorld,GraphicWorld,WorldSize,CameraCenter};
+use super::{PhysicWorld,GraphicWorld,WorldSize,CameraCenter,CameraZoom,Camera2D,CameraMode,IsometryF64,PhysicalShape,BattlefieldView,Gravity,BattlefieldEvent,BattlefieldEvents,WorldBoundary,CcdConfiguration,PhysicalJoint,PhysicalForce,QueryHit,PhysicalSpace,PhysicalCollisionGroups};
+use super::physic_world::{PhysicWorldAction,DEFAULT_TIMESTEP,DEFAULT_MAX_SUBSTEPS,DEFAULT_MOVEMENT_EPSILON,Vector2,Point2};
 
 #[derive(PartialEq,Clone)]
 enum BattlefieldAction
 {
-    AddEntity(Entity,String),
-    RemoveEntity(Entity),
+    AddEntity(Entity,String,Isometry<f64>),  //Implemented
+    RemoveEntity(Entity),                    //Implemented
+
+    MoveEntity(Entity,Isometry<f64>),                   //Implemented
+    MoveOffsetEntity(Entity,f64,f64),                   //Implemented
+    SetLinearVelocity(Entity,Vector2<f64>),             //Implemented
+    SetAngularVelocity(Entity,f64),                     //Implemented
+    ApplyForce(Entity,Vector2<f64>,Point2<f64>),        //Implemented
+    ApplyImpulse(Entity,Vector2<f64>,Point2<f64>),      //Implemented
+
+    AddJoint(String,Entity,Entity,PhysicalJoint),       //Implemented
+    RemoveJoint(String),                                //Implemented
+
+    AddForce(String,PhysicalForce),                     //Implemented
+    AddOneShotForce(PhysicalForce),                     //Implemented
+    RemoveForce(String),                                //Implemented
+
+    WakeUpEntity(Entity),                               //Implemented
+    SleepEntity(Entity),                                //Implemented
 
     MoveCamera(f64,f64),            //Implemented
+    ZoomCamera(f64,Option<(f64,f64)>),  //Implemented
+    RotateCamera(f64),              //Implemented
+    SetCameraMode(CameraMode),      //Implemented
+
+    SetView(BattlefieldView),       //Implemented
+    SetGravity(Gravity),            //Implemented
 
     AddLayer(Entity),        //Implemented
     RemoveLayerById(String),        //Implemented
@@ -20,14 +44,90 @@ pub struct BattlefieldState {
     actions: Vec<BattlefieldAction>,
 
     graphic_world: Entity,
-    physic_world: Entity
+    physic_world: Entity,
+
+    //Layer where every added entity has been placed, used to detach it on removal
+    entity_layers: HashMap<Entity,Entity>,
+
+    layers: Vec<Entity>
 }
 
 impl BattlefieldState
 {
     pub fn add_entity(&mut self, entity: Entity, layer: String, position: Isometry<f64>) {self.actions.push(BattlefieldAction::AddEntity(entity,layer,position));}
+    pub fn remove_entity(&mut self, entity: Entity) {self.actions.push(BattlefieldAction::RemoveEntity(entity));}
+
+    pub fn move_entity(&mut self, entity: Entity, position: Isometry<f64>) {self.actions.push(BattlefieldAction::MoveEntity(entity,position));}
+    pub fn move_entity_by_offset(&mut self, entity: Entity, offset: (f64,f64)) {self.actions.push(BattlefieldAction::MoveOffsetEntity(entity,offset.0,offset.1));}
+    pub fn set_linear_velocity(&mut self, entity: Entity, velocity: Vector2<f64>) {self.actions.push(BattlefieldAction::SetLinearVelocity(entity,velocity));}
+    pub fn set_angular_velocity(&mut self, entity: Entity, velocity: f64) {self.actions.push(BattlefieldAction::SetAngularVelocity(entity,velocity));}
+    ///The force is applied at a point in world coordinates and lasts for the next physics step
+    pub fn apply_force(&mut self, entity: Entity, force: Vector2<f64>, point: Point2<f64>) {self.actions.push(BattlefieldAction::ApplyForce(entity,force,point));}
+    ///The impulse is applied at a point in world coordinates
+    pub fn apply_impulse(&mut self, entity: Entity, impulse: Vector2<f64>, point: Point2<f64>) {self.actions.push(BattlefieldAction::ApplyImpulse(entity,impulse,point));}
+
+    ///Connect two entities, the joint is removed automatically when one of them is removed.
+    ///Adding a joint with an id already used replaces the old joint.
+    pub fn add_joint(&mut self, id: String, entity1: Entity, entity2: Entity, joint: PhysicalJoint) {self.actions.push(BattlefieldAction::AddJoint(id,entity1,entity2,joint));}
+    pub fn remove_joint(&mut self, id: String) {self.actions.push(BattlefieldAction::RemoveJoint(id));}
+
+    pub fn wake_up_entity(&mut self, entity: Entity) {self.actions.push(BattlefieldAction::WakeUpEntity(entity));}
+    ///The entity is not simulated until something wakes it up
+    pub fn sleep_entity(&mut self, entity: Entity) {self.actions.push(BattlefieldAction::SleepEntity(entity));}
+
+    ///Add a force generator, adding a force with an id already used replaces the old force.
+    ///One shot forces, as explosions, are added as with add_one_shot_force and the id is not used.
+    pub fn add_force(&mut self, id: String, force: PhysicalForce) {self.actions.push(BattlefieldAction::AddForce(id,force));}
+    ///The force is applied in the next physics step and then removed, so it has no id and cannot be removed
+    pub fn add_one_shot_force(&mut self, force: PhysicalForce) {self.actions.push(BattlefieldAction::AddOneShotForce(force));}
+    pub fn remove_force(&mut self, id: String) {self.actions.push(BattlefieldAction::RemoveForce(id));}
+    ///Entities hit by a ray, sorted by distance.
+    ///The colliders are the ones of the last physics step.
+    pub fn cast_ray(&self, ctx: &mut Context, origin: Point2<f64>, direction: Vector2<f64>, max_distance: f64, groups: PhysicalCollisionGroups)->Vec<QueryHit>
+    {
+        ctx.widget().clone::<PhysicalSpace>("physical_space").cast_ray(&origin,&direction,max_distance,&groups)
+    }
+    ///Entities hit by a shape moving from origin along the direction, sorted by distance
+    pub fn cast_shape(&self, ctx: &mut Context, shape: PhysicalShape, origin: Isometry<f64>, direction: Vector2<f64>, max_distance: f64, groups: PhysicalCollisionGroups)->Vec<QueryHit>
+    {
+        ctx.widget().clone::<PhysicalSpace>("physical_space").cast_shape(&shape,&origin,&direction,max_distance,&groups)
+    }
+
+    ///Entities that contain a point
+    pub fn entities_at_point(&self, ctx: &mut Context, point: Point2<f64>, groups: PhysicalCollisionGroups)->Vec<Entity>
+    {
+        ctx.widget().clone::<PhysicalSpace>("physical_space").entities_at_point(&point,&groups)
+    }
+    ///Entities that intersect a circle
+    pub fn entities_in_circle(&self, ctx: &mut Context, center: Point2<f64>, radius: f64, groups: PhysicalCollisionGroups)->Vec<Entity>
+    {
+        ctx.widget().clone::<PhysicalSpace>("physical_space").entities_in_circle(&center,radius,&groups)
+    }
+    ///Entities that intersect the rectangle from min to max
+    pub fn entities_in_rectangle(&self, ctx: &mut Context, min: Point2<f64>, max: Point2<f64>, groups: PhysicalCollisionGroups)->Vec<Entity>
+    {
+        ctx.widget().clone::<PhysicalSpace>("physical_space").entities_in_rectangle(&min,&max,&groups)
+    }
+
+    pub fn explode(&mut self, center: Point2<f64>, radius: f64, impulse: f64) {self.add_one_shot_force(PhysicalForce::Explosion{center: center,radius: radius,impulse: impulse});}
 
     pub fn move_camera(&mut self, position: (f64,f64)) {self.actions.push(BattlefieldAction::MoveCamera(position.0,position.1));}
+    ///Set the zoom, kept inside the camera_zoom limits.
+    ///When an anchor is given, as the cursor position inside the battlefield, the world point under it does not move.
+    pub fn zoom_camera(&mut self, zoom: f64, anchor: Option<(f64,f64)>) {self.actions.push(BattlefieldAction::ZoomCamera(zoom,anchor));}
+    ///Turn the camera around its center, the angle is in radians
+    pub fn rotate_camera(&mut self, rotation: f64) {self.actions.push(BattlefieldAction::RotateCamera(rotation));}
+    ///Keep the camera on an entity of the physic world, until stop_following is called.
+    ///The entity can move inside the dead zone without moving the camera, a smoothing lower than 1 makes the camera lag behind
+    ///and look_ahead moves the camera toward where the entity will be after that many seconds.
+    pub fn follow_entity(&mut self, entity: Entity, dead_zone: Option<(f64,f64)>, smoothing: f64, look_ahead: f64)
+    {
+        self.actions.push(BattlefieldAction::SetCameraMode(CameraMode::Follow{entity: entity,dead_zone: dead_zone,smoothing: smoothing,look_ahead: look_ahead}));
+    }
+    pub fn stop_following(&mut self) {self.actions.push(BattlefieldAction::SetCameraMode(CameraMode::Free));}
+
+    pub fn set_view(&mut self, view: BattlefieldView) {self.actions.push(BattlefieldAction::SetView(view));}
+    pub fn set_gravity(&mut self, gravity: Gravity) {self.actions.push(BattlefieldAction::SetGravity(gravity));}
 
     pub fn add_layer(&mut self, layer: Entity) {self.actions.push(BattlefieldAction::AddLayer(layer));}
     pub fn remove_layer_by_id(&mut self, id: String) {self.actions.push(BattlefieldAction::RemoveLayerById(id));}
@@ -35,6 +135,43 @@ impl BattlefieldState
 
 
 
+    ///The camera used to draw the battlefield
+    pub fn camera(&self, ctx: &mut Context)->Camera2D {Camera2D::from_widget(&ctx.get_widget(self.graphic_world))}
+    ///Convert a point relative to the battlefield, as the position of a mouse event, into world coordinates
+    pub fn screen_to_world(&self, ctx: &mut Context, point: (f64,f64))->(f64,f64) {self.camera(ctx).screen_to_world(point)}
+    ///Convert a point in world coordinates into a point relative to the battlefield
+    pub fn world_to_screen(&self, ctx: &mut Context, point: (f64,f64))->(f64,f64) {self.camera(ctx).world_to_screen(point)}
+
+    fn physic_world_action(&self, ctx: &mut Context, action: PhysicWorldAction)
+    {
+        ctx.get_widget(self.physic_world).get_mut::<Vec<PhysicWorldAction>>("physic_world_actions").push(action);
+    }
+
+    //Events produced by the physic world are shared through the battlefield_events property
+    //and forwarded to every layer that can receive them
+    fn dispatch_events(&mut self, ctx: &mut Context)
+    {
+        let events: Vec<BattlefieldEvent> = ctx.widget().get_mut::<BattlefieldEvents>("battlefield_events").drain(..).collect();
+        if events.is_empty() {return;}
+
+        if ctx.widget().clone::<WorldBoundary>("world_boundary") == WorldBoundary::Despawn
+        {
+            for event in &events
+            {
+                if let BattlefieldEvent::EntityLeftWorld(entity) = event {self.remove_entity(*entity);}
+            }
+        }
+
+        for layer in &self.layers
+        {
+            let mut layer = ctx.get_widget(*layer);
+            if layer.has::<BattlefieldEvents>("battlefield_events")
+            {
+                layer.set("battlefield_events",events.clone());
+            }
+        }
+    }
+
     pub fn process_actions(&mut self,registry: &mut Registry, ctx: &mut Context)
     {
         let actions: Vec<BattlefieldAction> = self.actions.drain(..).collect();
@@ -42,42 +179,151 @@ impl BattlefieldState
         {
             match action
             {
-                BattlefieldAction::AddEntity(entity,layer_name)=>
+                BattlefieldAction::AddEntity(entity,layer_name,position)=>
                 {
-                    if Some(_) = ctx.get_widget(entity).try_clone::<Isometry64>("physical_position") {}
-                    else
+                    ctx.get_widget(entity).set("physical_position",IsometryF64(position));
+                    if ctx.get_widget(entity).has::<IsometryF64>("previous_physical_position")
                     {
-                        ctx.get_widget(entity).set("physical_position",Isometry::new(Vector::new(0.0,0.0),0.0));
+                        ctx.get_widget(entity).set("previous_physical_position",IsometryF64(position));
                     }
-                    if let Some(layer) = ctx.entity_of_child(layer_name.as_str())
+                    let layer = ctx.entity_of_child(layer_name.as_str());
+                    match layer
                     {
-                        ctx.append_child_entity_to(entity,layer);
+                        Some(layer)=>
+                        {
+                            ctx.append_child_entity_to(entity,layer);
+                            self.entity_layers.insert(entity,layer);
+                            println!("Added entity to layer {}",&layer_name);
+                        }
+                        None=>println!("Cannot add entity to layer {}: id not found",&layer_name)
                     }
 
                     if ctx.get_widget(entity).has::<PhysicalShape>("physical_shape")
                     {
-                        ctx.append_child_entity_to(entity,self.physic_world);
+                        self.physic_world_action(ctx,PhysicWorldAction::AddEntity(layer,entity,position));
                     }
+                }
+                BattlefieldAction::RemoveEntity(entity)=>
+                {
+                    //The widget may not exist anymore once detached, so the physic world is notified first.
+                    //Entities that are not in the physic world are ignored by it.
+                    self.physic_world_action(ctx,PhysicWorldAction::RemoveEntity(entity));
 
+                    //The camera stops where it is when the entity it follows disappears
+                    if let CameraMode::Follow{entity: followed,..} = ctx.widget().clone::<CameraMode>("camera_mode")
+                    {
+                        if followed == entity {ctx.widget().set("camera_mode",CameraMode::Free);}
+                    }
 
-                    //TODO Add entity to physic engine if it have a physical shape and a physical position
-                    println!("Added entity to layer {}",&layer_name);
+                    match self.entity_layers.remove(&entity)
+                    {
+                        Some(layer)=>ctx.remove_child_from(entity,layer),
+                        None=>println!("Cannot remove entity {:#?}: it is not in any layer",entity)
+                    }
                 }
-                BattlefieldAction::RemoveEntity(entity)=>
+                BattlefieldAction::MoveEntity(entity,position)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::MoveEntity(entity,position));
+                }
+                BattlefieldAction::MoveOffsetEntity(entity,x,y)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::MoveOffsetEntity(entity,Point::new(x,y)));
+                }
+                BattlefieldAction::SetLinearVelocity(entity,velocity)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::SetLinearVelocity(entity,velocity));
+                }
+                BattlefieldAction::SetAngularVelocity(entity,velocity)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::SetAngularVelocity(entity,velocity));
+                }
+                BattlefieldAction::ApplyForce(entity,force,point)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::ApplyForce(entity,force,point));
+                }
+                BattlefieldAction::ApplyImpulse(entity,impulse,point)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::ApplyImpulse(entity,impulse,point));
+                }
+                BattlefieldAction::AddJoint(id,entity1,entity2,joint)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::AddJoint(id,entity1,entity2,joint));
+                }
+                BattlefieldAction::RemoveJoint(id)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::RemoveJoint(id));
+                }
+                BattlefieldAction::AddForce(id,force)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::AddForce(id,force));
+                }
+                BattlefieldAction::AddOneShotForce(force)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::AddOneShotForce(force));
+                }
+                BattlefieldAction::RemoveForce(id)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::RemoveForce(id));
+                }
+                BattlefieldAction::WakeUpEntity(entity)=>
+                {
+                    self.physic_world_action(ctx,PhysicWorldAction::WakeUpEntity(entity));
+                }
+                BattlefieldAction::SleepEntity(entity)=>
                 {
-                    //TODO C'e' bisogno di rimuovere l'entita' dal layer,
-                    //ma non trovo una funzione per trovare in che layer e' l'entita'
-                    //o per rimuovere ricorsivamente
+                    self.physic_world_action(ctx,PhysicWorldAction::SleepEntity(entity));
                 }
                 BattlefieldAction::MoveCamera(x,y)=>
                 {
+                    let camera = self.camera(ctx);
+                    let camera = Camera2D::new((x,y),camera.width(),camera.height(),camera.zoom(),camera.rotation());
+                    let (x,y) = if *ctx.widget().get::<bool>("clamp_camera")
+                    {
+                        camera.clamped(&ctx.widget().clone::<WorldSize>("world_size")).center()
+                    }
+                    else {(x,y)};
                     ctx.widget().set("camera_center",CameraCenter(x,y))
                 }
+                BattlefieldAction::ZoomCamera(zoom,anchor)=>
+                {
+                    let camera_zoom = ctx.widget().clone::<CameraZoom>("camera_zoom");
+                    let camera_zoom = CameraZoom{zoom: zoom,..camera_zoom};
+                    let new_zoom = camera_zoom.clamped();
+
+                    if let Some(anchor) = anchor
+                    {
+                        //The anchor is moved to the same world point before and after the zoom
+                        let camera = self.camera(ctx);
+                        let world_point = camera.screen_to_world(anchor);
+                        let zoomed_camera = Camera2D::new(camera.center(),camera.width(),camera.height(),new_zoom,camera.rotation());
+                        let zoomed_point = zoomed_camera.screen_to_world(anchor);
+                        let (x,y) = camera.center();
+                        ctx.widget().set("camera_center",CameraCenter(x + world_point.0 - zoomed_point.0,y + world_point.1 - zoomed_point.1));
+                    }
+                    ctx.widget().set("camera_zoom",CameraZoom{zoom: new_zoom,..camera_zoom});
+                }
+                BattlefieldAction::RotateCamera(rotation)=>
+                {
+                    ctx.widget().set("camera_rotation",rotation)
+                }
+                BattlefieldAction::SetCameraMode(camera_mode)=>
+                {
+                    ctx.widget().set("camera_mode",camera_mode)
+                }
+                BattlefieldAction::SetView(view)=>
+                {
+                    ctx.widget().set("battlefield_view",view)
+                }
+                BattlefieldAction::SetGravity(gravity)=>
+                {
+                    ctx.widget().set("gravity",gravity)
+                }
                 BattlefieldAction::AddLayer(layer)=>
                 {
                     if let Some(id) = ctx.get_widget(layer).try_clone::<String16>("id")
                     {
                         ctx.append_child_entity_to(layer,self.graphic_world);
+                        self.layers.push(layer);
                     }
                     else {println!("Cannot add layer {:#?} because id is not setted",layer);}
                 }
@@ -86,12 +332,14 @@ impl BattlefieldState
                     if let Some(layer) = ctx.entity_of_child(id.as_str())
                     {
                         ctx.remove_child_from(layer,self.graphic_world);
+                        self.layers.retain(|current| *current != layer);
                     }
                     else {println!("Cannot remove layer {}: id not found",id);}
                 }
                 BattlefieldAction::RemoveLayerByEntity(entity)=>
                 {
                     ctx.remove_child_from(entity,self.graphic_world);
+                    self.layers.retain(|current| *current != entity);
                 }
                 _=>{}
             }
@@ -104,33 +352,52 @@ impl State for BattlefieldState {
     {
         let mut graphic_world = GraphicWorld::new()
         .world_size(ctx.entity)
-        .camera_center(ctx.entity);
+        .camera_center(ctx.entity)
+        .camera_zoom(ctx.entity)
+        .camera_rotation(ctx.entity)
+        .clamp_camera(ctx.entity)
+        .interpolation_factor(ctx.entity);
 
-        for mut i in 0..self.actions.len()
+        let actions: Vec<BattlefieldAction> = self.actions.drain(..).collect();
+        for action in actions
         {
-            match self.actions[i]
+            match action
             {
                 BattlefieldAction::AddLayer(layer)=>
                 {
                     println!("Added layer");
                     graphic_world = graphic_world.layer(layer);
-                    self.actions.remove(i);
-                    i = i-1;
+                    self.layers.push(layer);
                 }
-                _=>{}
+                action=>self.actions.push(action)
             }
         }
 
-        self.physic_world = PhysicWorld::new().world_size(ctx.entity).build(&mut ctx.build_context());
+        self.physic_world = PhysicWorld::new()
+        .world_size(ctx.entity)
+        .timestep(ctx.entity)
+        .max_substeps(ctx.entity)
+        .battlefield_view(ctx.entity)
+        .gravity(ctx.entity)
+        .world_boundary(ctx.entity)
+        .ccd_configuration(ctx.entity)
+        .movement_epsilon(ctx.entity)
+        .interpolation_factor(ctx.entity)
+        .camera_center(ctx.entity)
+        .camera_mode(ctx.entity)
+        .physical_space(ctx.entity)
+        .battlefield_events(ctx.entity)
+        .build(&mut ctx.build_context());
         self.graphic_world = graphic_world.build(&mut ctx.build_context());
 
         ctx.append_child_entity_to(self.physic_world,ctx.entity);
         ctx.append_child_entity_to(self.graphic_world,ctx.entity);
         println!("Worlds initialized");
     }
-    fn update(&mut self, _: &mut Registry, ctx: &mut Context)
+    fn update(&mut self, registry: &mut Registry, ctx: &mut Context)
     {
-
+        self.process_actions(registry,ctx);
+        self.dispatch_events(ctx);
     }
 }
 
@@ -142,7 +409,35 @@ widget!(
     Battlefield<BattlefieldState>
     {
         world_size: WorldSize,
-        camera_center: CameraCenter
+        camera_center: CameraCenter,
+        ///Pixels per world unit, with the limits used by zoom_camera
+        camera_zoom: CameraZoom,
+        ///Rotation of the camera around the camera_center in radians
+        camera_rotation: f64,
+        ///Colliders of the physic world, used by the queries
+        physical_space: PhysicalSpace,
+        ///Set by follow_entity and stop_following
+        camera_mode: CameraMode,
+        ///Keep the visible area inside the world_size, when the world is smaller than the view it is centered
+        clamp_camera: bool,
+
+        ///Length in seconds of a single physics step, the simulation is paused when it is not positive
+        timestep: f64,
+        ///Maximum number of physics steps simulated in a single update, the simulation is paused when it is 0
+        max_substeps: u32,
+
+        battlefield_view: BattlefieldView,
+        ///By default the gravity follows the battlefield_view: none when seen from the top, downward when seen from the side
+        gravity: Gravity,
+        ///Tells if the border of the world is open, made of walls, wraps around or removes the entities that leave
+        world_boundary: WorldBoundary,
+        ccd_configuration: CcdConfiguration,
+        ///Minimum translation or rotation of an entity to send an EntityMoved event to the layers
+        movement_epsilon: f64,
+        ///Fraction of a physics step elapsed since the last one, used to interpolate the drawn positions
+        interpolation_factor: f64,
+
+        battlefield_events: BattlefieldEvents
     }
 );
 
@@ -158,6 +453,9 @@ impl Battlefield
 impl Template for Battlefield {
     fn template(self, id: Entity, ctx: &mut BuildContext) -> Self {
         self.name("Battlefield")
+        .timestep(DEFAULT_TIMESTEP)
+        .max_substeps(DEFAULT_MAX_SUBSTEPS)
+        .movement_epsilon(DEFAULT_MOVEMENT_EPSILON)
 
     }
 }
@@ -1,8 +1,170 @@
+use orbtk::prelude::*;
 
+use super::{CameraCenter,CameraZoom,WorldSize};
 
+///What the camera sees: a viewport of width by height pixels showing the world around center,
+///scaled by the zoom and turned by the rotation.
+///Screen points are relative to the top left corner of the GraphicWorld, world points are in world units.
+#[derive(Debug,Clone,PartialEq)]
 pub struct Camera2D
 {
     center: (f64,f64),
     width: f64,
-    height: f64
+    height: f64,
+    zoom: f64,
+    rotation: f64
+}
+
+impl Camera2D
+{
+    pub fn new(center: (f64,f64), width: f64, height: f64, zoom: f64, rotation: f64)->Self
+    {
+        Self
+        {
+            center: center,
+            width: width,
+            height: height,
+            zoom: zoom,
+            rotation: rotation
+        }
+    }
+
+    ///The camera of a widget with the camera_center, camera_zoom and camera_rotation properties, as the GraphicWorld.
+    ///When the widget has clamp_camera set, the camera is kept inside its world_size.
+    pub fn from_widget(widget: &WidgetContainer)->Self
+    {
+        let camera_center = widget.clone::<CameraCenter>("camera_center");
+        let bounds = widget.clone::<Rectangle>("bounds");
+        let camera = Self::new(
+            (camera_center.0,camera_center.1),
+            bounds.width(),
+            bounds.height(),
+            widget.try_clone::<CameraZoom>("camera_zoom").unwrap_or_default().clamped(),
+            widget.try_clone::<f64>("camera_rotation").unwrap_or(0.0)
+        );
+        match widget.try_clone::<bool>("clamp_camera")
+        {
+            Some(true)=>camera.clamped(&widget.clone::<WorldSize>("world_size")),
+            _=>camera
+        }
+    }
+
+    pub fn center(&self)->(f64,f64) {self.center}
+    pub fn width(&self)->f64 {self.width}
+    pub fn height(&self)->f64 {self.height}
+    pub fn zoom(&self)->f64 {self.zoom}
+    pub fn rotation(&self)->f64 {self.rotation}
+
+    ///Half width and half height, in world units, of the smallest axis aligned rectangle containing the visible area
+    pub fn visible_half_extents(&self)->(f64,f64)
+    {
+        let (half_width,half_height) = (self.width / 2.0 / self.zoom,self.height / 2.0 / self.zoom);
+        let (sin,cos) = self.rotation.sin_cos();
+        (
+            half_width * cos.abs() + half_height * sin.abs(),
+            half_width * sin.abs() + half_height * cos.abs()
+        )
+    }
+
+    ///The same camera moved so that the visible area does not leave the world, that goes from (0,0) to world_size.
+    ///On the sides where the world is smaller than the visible area, the world is centered.
+    pub fn clamped(&self, world_size: &WorldSize)->Self
+    {
+        let (half_width,half_height) = self.visible_half_extents();
+        let clamp = |center: f64, half_extent: f64, world_extent: f64|->f64
+        {
+            if world_extent <= half_extent * 2.0 {world_extent / 2.0}
+            else {center.max(half_extent).min(world_extent - half_extent)}
+        };
+        Self
+        {
+            center: (clamp(self.center.0,half_width,world_size.0),clamp(self.center.1,half_height,world_size.1)),
+            ..self.clone()
+        }
+    }
+
+    pub fn world_to_screen(&self, point: (f64,f64))->(f64,f64)
+    {
+        //The world is turned the opposite way of the camera
+        let (x,y) = (point.0 - self.center.0,point.1 - self.center.1);
+        let (sin,cos) = self.rotation.sin_cos();
+        (
+            (x * cos + y * sin) * self.zoom + self.width / 2.0,
+            (y * cos - x * sin) * self.zoom + self.height / 2.0
+        )
+    }
+
+    pub fn screen_to_world(&self, point: (f64,f64))->(f64,f64)
+    {
+        let (x,y) = ((point.0 - self.width / 2.0) / self.zoom,(point.1 - self.height / 2.0) / self.zoom);
+        let (sin,cos) = self.rotation.sin_cos();
+        (
+            x * cos - y * sin + self.center.0,
+            x * sin + y * cos + self.center.1
+        )
+    }
+
+    ///Tells if a screen point is inside the viewport
+    pub fn contains_screen_point(&self, point: (f64,f64))->bool
+    {
+        point.0 >= 0.0 && point.0 <= self.width && point.1 >= 0.0 && point.1 <= self.height
+    }
+
+    pub fn contains_world_point(&self, point: (f64,f64))->bool {self.contains_screen_point(self.world_to_screen(point))}
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use super::super::CameraMode;
+
+    const EPSILON: f64 = 1e-9;
+
+    fn assert_close(a: (f64,f64), b: (f64,f64))
+    {
+        assert!((a.0 - b.0).abs() < EPSILON && (a.1 - b.1).abs() < EPSILON,"{:?} != {:?}",a,b);
+    }
+
+    #[test]
+    fn screen_to_world_inverts_world_to_screen()
+    {
+        let camera = Camera2D::new((30.0,-12.0),800.0,600.0,2.5,0.7);
+        for point in [(0.0,0.0),(30.0,-12.0),(-45.5,120.25),(1000.0,3.0)].iter()
+        {
+            assert_close(camera.screen_to_world(camera.world_to_screen(*point)),*point);
+        }
+        for point in [(0.0,0.0),(400.0,300.0),(799.0,12.5)].iter()
+        {
+            assert_close(camera.world_to_screen(camera.screen_to_world(*point)),*point);
+        }
+        //The camera center is always in the middle of the screen
+        assert_close(camera.world_to_screen((30.0,-12.0)),(400.0,300.0));
+    }
+
+    #[test]
+    fn clamped_centers_a_world_smaller_than_the_view()
+    {
+        //The view covers 200 by 100 world units, the world only 100 by 50
+        let camera = Camera2D::new((-500.0,700.0),400.0,200.0,2.0,0.0);
+        assert_eq!(camera.clamped(&WorldSize(100.0,50.0)).center(),(50.0,25.0));
+    }
+
+    #[test]
+    fn clamped_keeps_the_view_inside_a_bigger_world()
+    {
+        let camera = Camera2D::new((-500.0,700.0),400.0,200.0,2.0,0.0);
+        assert_eq!(camera.clamped(&WorldSize(1000.0,500.0)).center(),(100.0,450.0));
+        let camera = Camera2D::new((300.0,200.0),400.0,200.0,2.0,0.0);
+        assert_eq!(camera.clamped(&WorldSize(1000.0,500.0)).center(),(300.0,200.0));
+    }
+
+    #[test]
+    fn follow_stays_still_inside_the_dead_zone()
+    {
+        let camera_mode = CameraMode::Follow{entity: Entity(0),dead_zone: Some((20.0,10.0)),smoothing: 1.0,look_ahead: 0.0};
+        assert_eq!(camera_mode.follow((50.0,50.0),(59.0,46.0),(0.0,0.0),1.0),(50.0,50.0));
+        //Outside the dead zone the target is brought back to its border
+        assert_close(camera_mode.follow((50.0,50.0),(65.0,50.0),(0.0,0.0),1.0),(55.0,50.0));
+    }
 }
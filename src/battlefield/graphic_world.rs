@@ -5,7 +5,7 @@ use crate::battlefield::BaseLayer;
 
 use std::collections::HashMap;
 
-use super::CameraCenter;
+use super::{CameraCenter,CameraZoom,Camera2D};
 
 enum GraphicWorldAction
 {
@@ -36,6 +36,13 @@ impl GraphicWorldState
     pub fn remove_layer_by_id(&mut self, id: String) {self.actions.push(GraphicWorldAction::RemoveLayerById(id));}
     pub fn remove_layer_by_entity(&mut self, entity: Entity) {self.actions.push(GraphicWorldAction::RemoveLayerByEntity(entity));}
 
+    ///The camera as it is used to place the children
+    pub fn camera(&self, ctx: &mut Context)->Camera2D {Camera2D::from_widget(&ctx.widget())}
+    ///Convert a point relative to the GraphicWorld into world coordinates
+    pub fn screen_to_world(&self, ctx: &mut Context, point: (f64,f64))->(f64,f64) {self.camera(ctx).screen_to_world(point)}
+    ///Convert a point in world coordinates into a point relative to the GraphicWorld
+    pub fn world_to_screen(&self, ctx: &mut Context, point: (f64,f64))->(f64,f64) {self.camera(ctx).world_to_screen(point)}
+
 
     pub fn process_actions(&mut self,registry: &mut Registry, ctx: &mut Context)
     {
@@ -90,7 +97,17 @@ widget!(
     {
         world_size: WorldSize,
 
-        camera_center: CameraCenter
+        camera_center: CameraCenter,
+        ///Scale from world units to pixels, applied to the positions and the bounds of the children
+        camera_zoom: CameraZoom,
+        ///Rotation of the camera around its center in radians, the children are drawn turned the opposite way
+        camera_rotation: f64,
+        ///Keep the camera view inside the world, centering the world when it is smaller than the view
+        clamp_camera: bool,
+
+        ///Fraction of a physics step elapsed since the last one.
+        ///Children with a previous_physical_position are drawn between it and the physical_position.
+        interpolation_factor: f64
     }
 );
 
@@ -125,6 +142,73 @@ impl CameraLayout {
     pub fn new() -> Self {
         CameraLayout::default()
     }
+
+    //Place a child with a physical position where the camera sees it
+    fn arrange_physical_child(
+        &self,
+        render_context_2_d: &mut RenderContext2D,
+        camera: &Camera2D,
+        interpolation_factor: f64,
+        child: Entity,
+        ecm: &mut EntityComponentManager<Tree, StringComponentStore>,
+        layouts: &BTreeMap<Entity, Box<dyn Layout>>,
+        theme: &Theme,
+    ) {
+        let (child_layout,physical_position) = match (layouts.get(&child),try_component::<IsometryF64>(ecm, child, "physical_position"))
+        {
+            (Some(child_layout),Some(physical_position))=>(child_layout,physical_position),
+            _=>return
+        };
+
+        //Between two physics steps the child is placed at an interpolated pose
+        let physical_position = match try_component::<IsometryF64>(ecm, child, "previous_physical_position")
+        {
+            Some(previous_position)=>IsometryF64(previous_position.interpolate(&physical_position,interpolation_factor)),
+            None=>physical_position
+        };
+        if let Some(render_position) = component_try_mut::<IsometryF64>(ecm, child, "render_position")
+        {
+            *render_position = physical_position.clone();
+        }
+        if let Some(render_scale) = component_try_mut::<f64>(ecm, child, "render_scale")
+        {
+            *render_scale = camera.zoom();
+        }
+        if let Some(render_rotation) = component_try_mut::<f64>(ecm, child, "render_rotation")
+        {
+            *render_rotation = -camera.rotation();
+        }
+
+        //The drawing zone start from (0,0) to (bounds.width,bounds.height), so the physical position
+        //is moved relative to the camera center, turned against the camera rotation and scaled by the zoom.
+        //Children outside the view are placed too, otherwise they would stay drawn where they were last seen.
+        let adjusted_position = camera.world_to_screen((physical_position.translation.vector.x,physical_position.translation.vector.y));
+
+        //The size given by the child layout is never scaled, so the bounds are scaled only once
+        //even when the child is not arranged again
+        let child_size = child_layout.arrange(
+            render_context_2_d,
+            (
+                self.desired_size.borrow().width(),
+                self.desired_size.borrow().height(),
+            ),
+            child,
+            ecm,
+            layouts,
+            theme,
+        );
+
+        //The child is drawn around the center of its bounds, that must be on the physical position
+        if let Some(child_bounds) = component_try_mut::<Rectangle>(ecm, child, "bounds")
+        {
+            let (width,height) = (child_size.0 * camera.zoom(),child_size.1 * camera.zoom());
+            child_bounds.set_x(adjusted_position.0 - width / 2.0);
+            child_bounds.set_y(adjusted_position.1 - height / 2.0);
+            child_bounds.set_width(width);
+            child_bounds.set_height(height);
+        }
+        else{println!("Warning: cannot set bounds");}
+    }
 }
 
 impl Layout for CameraLayout {
@@ -181,65 +265,66 @@ impl Layout for CameraLayout {
         mark_as_dirty("bounds", entity, ecm);
 
         //This is what the camera see actually
-        let camera_view =
+        let camera =
         {
             let camera_center = component::<CameraCenter>(ecm, entity, "camera_center");
             let bounds = component::<Rectangle>(ecm, entity, "bounds");
-            let top_left_corner = Point::new(camera_center.0 - (bounds.width()/2.0),camera_center.1 - (bounds.height()/2.0));
-            Rectangle::new(top_left_corner,bounds.width(),bounds.height())
+            let camera = Camera2D::new(
+                (camera_center.0,camera_center.1),
+                bounds.width(),
+                bounds.height(),
+                component_or_default::<CameraZoom>(ecm, entity, "camera_zoom").clamped(),
+                component_or_default::<f64>(ecm, entity, "camera_rotation")
+            );
+            if component_or_default::<bool>(ecm, entity, "clamp_camera")
+            {
+                camera.clamped(&component::<WorldSize>(ecm, entity, "world_size"))
+            }
+            else {camera}
         };
 
+        let interpolation_factor = try_component::<f64>(ecm, entity, "interpolation_factor").unwrap_or(1.0);
+        let size = (self.desired_size.borrow().width(),self.desired_size.borrow().height());
+
         for index in 0..ecm.entity_store().children[&entity].len() {
             let child = ecm.entity_store().children[&entity][index];
             if let Some(child_layout) = layouts.get(&child) {
-                if let Some(physical_position) = try_component::<IsometryF64>(ecm, child, "physical_position")
+                if try_component::<IsometryF64>(ecm, child, "physical_position").is_some()
                 {
-                    //If the child have a phisical position, it must be inside the camera view
-                    let point = Point::new(physical_position.translation.vector.x,physical_position.translation.vector.y);
-                    if camera_view.contains(point)
-                    {
-                        //The drawing zone start from (0,0) to (bounds.width,bounds.height), so the physical position
-                        //need to be adjusted with the drawing posision.
-                        //To do this, i simply translate the physical position to align with the (0,0) position.
-                        let adjusted_position =
-                        (
-                            physical_position.translation.vector.x - camera_view.x(),
-                            physical_position.translation.vector.y - camera_view.y()
-                        );
-                        child_layout.arrange(
-                            render_context_2_d,
-                            (
-                                self.desired_size.borrow().width(),
-                                self.desired_size.borrow().height(),
-                            ),
-                            child,
-                            ecm,
-                            layouts,
-                            theme,
-                        );
-
-                        if let Some(child_bounds) = component_try_mut::<Rectangle>(ecm, child, "bounds")
-                        {
-                            child_bounds.set_x(adjusted_position.0);
-                            child_bounds.set_y(adjusted_position.1);
-                        }
-                        else{println!("Warning: cannot set bounds");}
-                    }
+                    self.arrange_physical_child(render_context_2_d, &camera, interpolation_factor, child, ecm, layouts, theme);
                 }
                 else
                 {
-                    //Otherwise the child is something placed on absolute position on the camera
+                    //Otherwise the child is something placed on absolute position on the camera, as a layer
                     child_layout.arrange(
                         render_context_2_d,
-                        (
-                            self.desired_size.borrow().width(),
-                            self.desired_size.borrow().height(),
-                        ),
+                        size,
                         child,
                         ecm,
                         layouts,
                         theme,
                     );
+
+                    //The entities are children of the layers: the layer covers the whole GraphicWorld,
+                    //so that the screen positions given by the camera are relative to it too
+                    let grandchildren = ecm.entity_store().children[&child].clone();
+                    let physical_children: Vec<Entity> = grandchildren.into_iter()
+                        .filter(|grandchild| try_component::<IsometryF64>(ecm, *grandchild, "physical_position").is_some())
+                        .collect();
+                    if !physical_children.is_empty()
+                    {
+                        if let Some(child_bounds) = component_try_mut::<Rectangle>(ecm, child, "bounds")
+                        {
+                            child_bounds.set_x(0.0);
+                            child_bounds.set_y(0.0);
+                            child_bounds.set_width(size.0);
+                            child_bounds.set_height(size.1);
+                        }
+                        for grandchild in physical_children
+                        {
+                            self.arrange_physical_child(render_context_2_d, &camera, interpolation_factor, grandchild, ecm, layouts, theme);
+                        }
+                    }
                 }
             }
         }
@@ -6,6 +6,21 @@ pub use shape_widget::{ShapeWidget};
 pub mod base_layer;
 pub use base_layer::BaseLayer;
 
+pub mod trigger_area;
+pub use trigger_area::TriggerArea;
+
+pub mod physical_joint;
+pub use physical_joint::{PhysicalJoint,JointMotor};
+
+pub mod physical_force;
+pub use physical_force::PhysicalForce;
+
+pub mod physical_query;
+pub use physical_query::{QueryHit,PhysicalSpace,CollisionSpace};
+
+
+pub mod camera_2d;
+pub use camera_2d::Camera2D;
 
 pub mod physic_world;
 use physic_world::PhysicWorld;
@@ -21,18 +36,29 @@ use nphysics2d::object::DefaultBodyHandle;
 use nphysics2d::object::DefaultColliderHandle;
 
 use ncollide2d::shape::ShapeHandle;
-use ncollide2d::shape::Ball;
+use ncollide2d::shape::{Ball,Cuboid,ConvexPolygon,Capsule,Segment,Compound};
+use ncollide2d::pipeline::CollisionGroups;
 
 use nphysics2d::object::ColliderDesc;
 use nphysics2d::object::Collider;
+use nphysics2d::object::BodyStatus;
+use nphysics2d::object::ActivationStatus;
+use nphysics2d::solver::IntegrationParameters;
 
 use nphysics2d::math::Isometry;
 use nphysics2d::math::Vector;
+use nphysics2d::math::Point;
 
-#[derive(Debug,Clone,PartialEq)]
+#[derive(Debug,Clone)]
 pub enum PhysicalShape
 {
-    Ball2D(Ball<f64>)
+    Ball2D(Ball<f64>),
+    Cuboid2D(Cuboid<f64>),
+    ConvexPolygon2D(ConvexPolygon<f64>),
+    Capsule2D(Capsule<f64>),
+    Segment2D(Segment<f64>),
+    ///Shape made of several parts, each placed with an isometry local to the body
+    Compound2D(Vec<(Isometry<f64>,PhysicalShape)>)
 }
 
 impl Default for PhysicalShape
@@ -40,26 +66,189 @@ impl Default for PhysicalShape
     fn default()->Self {Self::Ball2D(Ball::new(5.0))}
 }
 
+//ConvexPolygon does not implement PartialEq, so the comparison is done on its points
+impl PartialEq for PhysicalShape
+{
+    fn eq(&self, other: &Self)->bool
+    {
+        match (self,other)
+        {
+            (Self::Ball2D(a),Self::Ball2D(b))=>a == b,
+            (Self::Cuboid2D(a),Self::Cuboid2D(b))=>a == b,
+            (Self::ConvexPolygon2D(a),Self::ConvexPolygon2D(b))=>a.points() == b.points(),
+            (Self::Capsule2D(a),Self::Capsule2D(b))=>a == b,
+            (Self::Segment2D(a),Self::Segment2D(b))=>a == b,
+            (Self::Compound2D(a),Self::Compound2D(b))=>a == b,
+            _=>false
+        }
+    }
+}
 
 impl PhysicalShape
 {
-    pub fn to_collider(self,parent_handle: DefaultBodyHandle)->Collider<f64,DefaultBodyHandle>
+    pub fn ball(radius: f64)->Self {Self::Ball2D(Ball::new(radius))}
+    pub fn cuboid(half_width: f64, half_height: f64)->Self {Self::Cuboid2D(Cuboid::new(Vector::new(half_width,half_height)))}
+    ///Returns None if the convex hull of the points cannot be computed
+    pub fn convex_polygon(points: &[Point<f64>])->Option<Self> {ConvexPolygon::try_from_points(points).map(Self::ConvexPolygon2D)}
+    pub fn capsule(half_height: f64, radius: f64)->Self {Self::Capsule2D(Capsule::new(half_height,radius))}
+    pub fn segment(a: Point<f64>, b: Point<f64>)->Self {Self::Segment2D(Segment::new(a,b))}
+    ///Returns None if there are no parts. Parts that are compounds are flattened into this one.
+    pub fn compound(parts: Vec<(Isometry<f64>,PhysicalShape)>)->Option<Self>
+    {
+        let parts = Self::flatten_parts(&Isometry::identity(),&parts);
+        if parts.is_empty() {None} else {Some(Self::Compound2D(parts))}
+    }
+
+    //ncollide cannot build a compound containing another compound, so nested parts are moved
+    //in the outer compound by combining their isometries
+    fn flatten_parts(isometry: &Isometry<f64>, parts: &[(Isometry<f64>,PhysicalShape)])->Vec<(Isometry<f64>,PhysicalShape)>
+    {
+        parts.iter()
+        .flat_map(|(part_isometry,shape)|
+        {
+            let part_isometry = isometry * part_isometry;
+            match shape
+            {
+                Self::Compound2D(inner_parts)=>Self::flatten_parts(&part_isometry,inner_parts),
+                _=>vec![(part_isometry,shape.clone())]
+            }
+        })
+        .collect()
+    }
+
+    ///Tells if the shape is a compound without parts, from which no collider can be built
+    pub fn is_empty(&self)->bool
+    {
+        match self
+        {
+            Self::Compound2D(parts)=>parts.iter().all(|(_,shape)|shape.is_empty()),
+            _=>false
+        }
+    }
+
+    ///Segments have no area, so no mass can be computed from a density for them or for a compound made with them
+    pub fn has_area(&self)->bool
+    {
+        match self
+        {
+            Self::Segment2D(_)=>false,
+            Self::Compound2D(parts)=>parts.iter().all(|(_,shape)|shape.has_area()),
+            _=>true
+        }
+    }
+
+    pub fn to_shape_handle(&self)->ShapeHandle<f64>
     {
         match self
         {
-            Self::Ball2D(ball)=>
+            Self::Ball2D(ball)=>ShapeHandle::new(ball.clone()),
+            Self::Cuboid2D(cuboid)=>ShapeHandle::new(cuboid.clone()),
+            Self::ConvexPolygon2D(polygon)=>ShapeHandle::new(polygon.clone()),
+            Self::Capsule2D(capsule)=>ShapeHandle::new(capsule.clone()),
+            Self::Segment2D(segment)=>ShapeHandle::new(segment.clone()),
+            Self::Compound2D(parts)=>
             {
-                let shape = ShapeHandle::new(ball);
-                ColliderDesc::new(shape)
-                .build(BodyPartHandle(parent_handle,0))
+                let parts = Self::flatten_parts(&Isometry::identity(),parts).iter()
+                .map(|(isometry,shape)|(isometry.clone(),shape.to_shape_handle()))
+                .collect();
+                ShapeHandle::new(Compound::new(parts))
             }
         }
+    }
+
+    pub fn to_collider_desc(&self)->ColliderDesc<f64>
+    {
+        ColliderDesc::new(self.to_shape_handle())
+    }
 
+    pub fn to_collider(self,parent_handle: DefaultBodyHandle)->Collider<f64,DefaultBodyHandle>
+    {
+        self.to_collider_desc()
+        .build(BodyPartHandle(parent_handle,0))
     }
 }
 
 into_property_source!(PhysicalShape);
 
+///How a physical entity is moved by the simulation.
+///Static bodies never move, kinematic bodies follow their physical_position exactly,
+///while dynamic bodies are moved by forces and contacts.
+#[derive(Debug,Clone,PartialEq)]
+pub enum PhysicalBodyStatus
+{
+    Static,
+    Kinematic,
+    Dynamic
+}
+impl Default for PhysicalBodyStatus
+{
+    fn default()->Self {Self::Dynamic}
+}
+into_property_source!(PhysicalBodyStatus);
+
+impl PhysicalBodyStatus
+{
+    pub fn to_body_status(&self)->BodyStatus
+    {
+        match self
+        {
+            Self::Static=>BodyStatus::Static,
+            Self::Kinematic=>BodyStatus::Kinematic,
+            Self::Dynamic=>BodyStatus::Dynamic
+        }
+    }
+}
+
+///Kinetic energy under which a body falls asleep, with None the body never sleeps.
+#[derive(Debug,Clone,PartialEq)]
+pub struct SleepThreshold(pub Option<f64>);
+impl Default for SleepThreshold
+{
+    fn default()->Self {Self(Some(ActivationStatus::<f64>::default_threshold()))}
+}
+into_property_source!(SleepThreshold);
+
+///Collision groups of a physical entity, every group is a number between 0 and 29.
+///A list that is None is inherited from the layer of the entity, or from the ncollide2d defaults
+///(member of every group, interacting with every group).
+///Setting the whitelist to an empty list makes the entity not collide with anything.
+#[derive(Debug,Default,Clone,PartialEq)]
+pub struct PhysicalCollisionGroups
+{
+    pub membership: Option<Vec<usize>>,
+    pub whitelist: Option<Vec<usize>>,
+    pub blacklist: Option<Vec<usize>>
+}
+into_property_source!(PhysicalCollisionGroups);
+
+impl PhysicalCollisionGroups
+{
+    pub fn new()->Self {Self::default()}
+    pub fn membership(mut self, groups: Vec<usize>)->Self {self.membership = Some(groups); self}
+    pub fn whitelist(mut self, groups: Vec<usize>)->Self {self.whitelist = Some(groups); self}
+    pub fn blacklist(mut self, groups: Vec<usize>)->Self {self.blacklist = Some(groups); self}
+
+    ///Fill the lists that are not set with the ones of the defaults
+    pub fn inherit(self, defaults: &Self)->Self
+    {
+        Self
+        {
+            membership: self.membership.or_else(|| defaults.membership.clone()),
+            whitelist: self.whitelist.or_else(|| defaults.whitelist.clone()),
+            blacklist: self.blacklist.or_else(|| defaults.blacklist.clone())
+        }
+    }
+
+    pub fn to_collision_groups(&self)->CollisionGroups
+    {
+        let mut collision_groups = CollisionGroups::new();
+        if let Some(membership) = &self.membership {collision_groups = collision_groups.with_membership(membership);}
+        if let Some(whitelist) = &self.whitelist {collision_groups = collision_groups.with_whitelist(whitelist);}
+        if let Some(blacklist) = &self.blacklist {collision_groups = collision_groups.with_blacklist(blacklist);}
+        collision_groups
+    }
+}
+
 #[derive(Debug,PartialEq,Clone)]
 pub struct IsometryF64(Isometry<f64>);
 
@@ -87,16 +276,45 @@ impl DerefMut for IsometryF64 {
     }
 }
 
+impl IsometryF64
+{
+    ///Pose between self (factor 0.0) and other (factor 1.0), rotating along the shortest angle
+    pub fn interpolate(&self, other: &Isometry<f64>, factor: f64)->Isometry<f64>
+    {
+        let translation = self.translation.vector.lerp(&other.translation.vector,factor);
+        let angle = self.rotation.angle() + self.rotation.angle_to(&other.rotation) * factor;
+        Isometry::new(translation,angle)
+    }
+}
+
 
 
 #[derive(Clone,PartialEq,Debug)]
 pub enum BattlefieldEvent
 {
-    EntityMoved(Entity,Isometry<f64>)
+    EntityMoved(Entity,Isometry<f64>),
+
+    ContactStarted(Entity,Entity),
+    ContactStopped(Entity,Entity),
+    ProximityEntered(Entity,Entity),
+    ProximityLeft(Entity,Entity),
+
+    ///Trigger area entity, area name and the entity that entered the area
+    AreaEntered(Entity,String,Entity),
+    ///Trigger area entity, area name and the entity that left the area
+    AreaLeft(Entity,String,Entity),
+
+    EntityLeftWorld(Entity),
+
+    ///The entity stopped moving and is not simulated until it is woken up
+    EntitySlept(Entity),
+    EntityWoke(Entity)
 }
 
 into_property_source!(BattlefieldEvent);
 
+pub type BattlefieldEvents = Vec<BattlefieldEvent>;
+
 #[derive(Clone,PartialEq,Debug)]
 pub enum LayerType
 {
@@ -124,11 +342,203 @@ impl Default for BattlefieldView
 {
     fn default()->Self {Self::Top}
 }
+into_property_source!(BattlefieldView);
+
+///Gravity applied when the battlefield is seen from the side.
+///The y axis points down on the screen, so the value is positive.
+pub const SIDE_VIEW_GRAVITY: f64 = 9.81;
+
+impl BattlefieldView
+{
+    pub fn gravity(&self)->Vector<f64>
+    {
+        match self
+        {
+            Self::Top=>Vector::new(0.0,0.0),
+            Self::Side=>Vector::new(0.0,SIDE_VIEW_GRAVITY)
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Gravity
+{
+    ///Use the gravity of the current BattlefieldView
+    FromView,
+    Custom(f64,f64)
+}
+impl Default for Gravity
+{
+    fn default()->Self {Self::FromView}
+}
+into_property_source!(Gravity);
+
+impl Gravity
+{
+    pub fn vector(&self, view: &BattlefieldView)->Vector<f64>
+    {
+        match self
+        {
+            Self::FromView=>view.gravity(),
+            Self::Custom(x,y)=>Vector::new(*x,*y)
+        }
+    }
+}
 
 #[derive(Debug,Default,Clone,PartialEq)]
 pub struct WorldSize(f64,f64);
 into_property_source!(WorldSize);
 
+///What happens to the entities at the border of the world, that goes from (0,0) to WorldSize.
+///In every mode, an entity that leaves the world emits an EntityLeftWorld event.
+///A world without a positive width and height has no border: nothing is checked and no wall is built.
+#[derive(Debug,Clone,PartialEq)]
+pub enum WorldBoundary
+{
+    ///Entities can drift out of the world
+    Open,
+    ///Static walls surround the world
+    Walls,
+    ///Entities leaving from a side come back from the opposite side
+    Wrap,
+    ///Entities leaving the world are removed from the battlefield
+    Despawn
+}
+impl Default for WorldBoundary
+{
+    fn default()->Self {Self::Open}
+}
+into_property_source!(WorldBoundary);
+
+///Continuous collision detection settings of the physic world,
+///used by the entities that have the ccd_enabled property set to true.
+#[derive(Debug,Clone,PartialEq)]
+pub struct CcdConfiguration
+{
+    ///Maximum number of substeps used to resolve the impacts of a step
+    pub max_substeps: usize,
+    ///Maximum number of iterations used to correct the positions after an impact
+    pub max_position_iterations: usize,
+    ///Use ccd also for the bodies that are already penetrating
+    pub on_penetration: bool,
+    ///Emit the proximity events of sensors at every ccd substep
+    pub multiple_substep_sensor_events: bool
+}
+impl Default for CcdConfiguration
+{
+    fn default()->Self
+    {
+        Self
+        {
+            max_substeps: 1,
+            max_position_iterations: 10,
+            on_penetration: false,
+            multiple_substep_sensor_events: false
+        }
+    }
+}
+into_property_source!(CcdConfiguration);
+
+impl CcdConfiguration
+{
+    pub fn apply(&self, parameters: &mut IntegrationParameters<f64>)
+    {
+        parameters.max_ccd_substeps = self.max_substeps;
+        parameters.max_ccd_position_iterations = self.max_position_iterations;
+        parameters.ccd_on_penetration_enabled = self.on_penetration;
+        parameters.multiple_ccd_substep_sensor_events_enabled = self.multiple_substep_sensor_events;
+    }
+}
+
 #[derive(Debug,Default,Clone,PartialEq)]
 pub struct CameraCenter(f64,f64);
 into_property_source!(CameraCenter);
+
+///Scale from world units to pixels, kept between min and max.
+///A zoom greater than 1 shows a smaller part of the world, bigger.
+#[derive(Debug,Clone,PartialEq)]
+pub struct CameraZoom
+{
+    pub zoom: f64,
+    pub min: f64,
+    pub max: f64
+}
+impl Default for CameraZoom
+{
+    fn default()->Self
+    {
+        Self
+        {
+            zoom: 1.0,
+            min: 0.1,
+            max: 10.0
+        }
+    }
+}
+into_property_source!(CameraZoom);
+
+impl CameraZoom
+{
+    pub fn new(zoom: f64)->Self {Self{zoom: zoom,..Self::default()}}
+    pub fn limits(mut self, min: f64, max: f64)->Self
+    {
+        self.min = min;
+        self.max = max;
+        self
+    }
+    ///The zoom inside the limits
+    pub fn clamped(&self)->f64 {self.zoom.max(self.min).min(self.max)}
+}
+
+///Tells if the camera is moved only by move_camera or follows an entity.
+#[derive(Debug,Clone,PartialEq)]
+pub enum CameraMode
+{
+    Free,
+    ///The camera_center moves toward the position of the entity at every update
+    Follow
+    {
+        entity: Entity,
+        ///Width and height of the rectangle around the camera center where the entity moves without moving the camera
+        dead_zone: Option<(f64,f64)>,
+        ///Fraction of the distance to the target covered in a physics step, 1 keeps the target at the center
+        smoothing: f64,
+        ///Seconds of movement at the current velocity added to the position of the entity, to see where it is going
+        look_ahead: f64
+    }
+}
+impl Default for CameraMode
+{
+    fn default()->Self {Self::Free}
+}
+into_property_source!(CameraMode);
+
+impl CameraMode
+{
+    ///The new camera center when the followed entity is in position and moves at velocity,
+    ///steps is the time elapsed since the last update measured in physics steps
+    pub fn follow(&self, center: (f64,f64), position: (f64,f64), velocity: (f64,f64), steps: f64)->(f64,f64)
+    {
+        match self
+        {
+            Self::Free=>center,
+            Self::Follow{dead_zone,smoothing,look_ahead,..}=>
+            {
+                let target = (position.0 + velocity.0 * look_ahead,position.1 + velocity.1 * look_ahead);
+                //Inside the dead zone the camera stays still, outside it moves just enough to bring the target back to its border
+                let (half_width,half_height) = match dead_zone
+                {
+                    Some((width,height))=>(width / 2.0,height / 2.0),
+                    None=>(0.0,0.0)
+                };
+                let offset = (
+                    (target.0 - center.0).signum() * ((target.0 - center.0).abs() - half_width).max(0.0),
+                    (target.1 - center.1).signum() * ((target.1 - center.1).abs() - half_height).max(0.0)
+                );
+                //The smoothing is scaled by the elapsed time, so that the camera lag does not depend on the frame rate
+                let smoothing = 1.0 - (1.0 - smoothing.max(0.0).min(1.0)).powf(steps);
+                (center.0 + offset.0 * smoothing,center.1 + offset.1 * smoothing)
+            }
+        }
+    }
+}
@@ -1,59 +1,811 @@
 extern crate nalgebra as na;
 
-pub use na::Vector2;
+pub use na::{Vector2,Point2};
 pub use ncollide2d::math::Isometry;
 
 use ncollide2d::pipeline::{ContactEvent,ProximityEvent};
-use nphysics2d::object::{DefaultBodySet, DefaultColliderSet,RigidBodyDesc,DefaultBodyHandle,DefaultColliderHandle};
-use nphysics2d::force_generator::DefaultForceGeneratorSet;
-use nphysics2d::joint::DefaultJointConstraintSet;
-use nphysics2d::world::{DefaultMechanicalWorld, DefaultGeometricalWorld};
+use ncollide2d::query::Proximity;
+use nphysics2d::object::{DefaultBodySet,RigidBodyDesc,RigidBody,Body,DefaultBodyHandle,DefaultColliderHandle,BodyPartHandle,BodyStatus,Collider};
+use nphysics2d::algebra::ForceType;
+use nphysics2d::material::{MaterialHandle,BasicMaterial};
+use nphysics2d::force_generator::{DefaultForceGeneratorSet,DefaultForceGeneratorHandle};
+use nphysics2d::joint::{DefaultJointConstraintSet,DefaultJointConstraintHandle};
+use nphysics2d::world::DefaultMechanicalWorld;
 
 use orbtk::prelude::*;
 
-use crate::battlefield::PhysicalShape;
+use crate::battlefield::{PhysicalShape,PhysicalCollisionGroups,PhysicalBodyStatus,SleepThreshold,PhysicalJoint,PhysicalForce,PhysicalSpace,CollisionSpace};
 use crate::battlefield::IsometryF64;
-use crate::battlefield::BattlefieldEvent;
+use crate::battlefield::{BattlefieldEvent,BattlefieldEvents};
 use crate::battlefield::Layer;
-use crate::battlefield::WorldSize;
+use crate::battlefield::{WorldSize,WorldBoundary,CcdConfiguration};
+use crate::battlefield::{BattlefieldView,Gravity};
+use crate::battlefield::{CameraCenter,CameraMode};
 use std::ops::Deref;
+use std::collections::HashSet;
+use std::time::Instant;
 
 
+pub const DEFAULT_TIMESTEP: f64 = 1.0 / 60.0;
+pub const DEFAULT_MAX_SUBSTEPS: u32 = 5;
+///Minimum translation or rotation of a body to emit an EntityMoved event
+pub const DEFAULT_MOVEMENT_EPSILON: f64 = 0.01;
+
 #[derive(Clone)]
 struct PhysicalEntity
 {
     entity: Entity,
+    layer: Option<Entity>,
     collider: DefaultColliderHandle,
-    rigid_body: DefaultBodyHandle
+    rigid_body: DefaultBodyHandle,
+
+    //Only trigger areas have a name
+    area_name: Option<String>,
+
+    //Material currently applied to the rigid body and the collider
+    material: PhysicalMaterial,
+    ccd_enabled: bool,
+    body_status: PhysicalBodyStatus,
+    sleep_threshold: SleepThreshold,
+    //Used to notify when the body falls asleep or wakes up
+    sleeping: bool,
+    //Position sent with the last EntityMoved event
+    notified_position: Isometry<f64>,
+    //Positions before and after the last step, used to interpolate the rendering between steps
+    previous_position: Isometry<f64>,
+    current_position: Isometry<f64>
+}
+
+///Thickness of the walls built around the world
+pub const BOUNDARY_THICKNESS: f64 = 10.0;
+
+pub const DEFAULT_DENSITY: f64 = 1.0;
+pub const DEFAULT_FRICTION: f64 = 0.5;
+pub const DEFAULT_RESTITUTION: f64 = 0.0;
+pub const DEFAULT_LINEAR_DAMPING: f64 = 0.0;
+pub const DEFAULT_ANGULAR_DAMPING: f64 = 0.0;
+
+///Material of a physical entity, read from the optional density, friction, restitution,
+///linear_damping and angular_damping properties of the widget.
+#[derive(Clone,PartialEq,Debug)]
+struct PhysicalMaterial
+{
+    density: f64,
+    friction: f64,
+    restitution: f64,
+    linear_damping: f64,
+    angular_damping: f64
+}
+
+impl PhysicalMaterial
+{
+    fn from_widget(widget: &WidgetContainer)->Self
+    {
+        Self
+        {
+            density: widget.try_clone::<f64>("density").unwrap_or(DEFAULT_DENSITY),
+            friction: widget.try_clone::<f64>("friction").unwrap_or(DEFAULT_FRICTION),
+            restitution: widget.try_clone::<f64>("restitution").unwrap_or(DEFAULT_RESTITUTION),
+            linear_damping: widget.try_clone::<f64>("linear_damping").unwrap_or(DEFAULT_LINEAR_DAMPING),
+            angular_damping: widget.try_clone::<f64>("angular_damping").unwrap_or(DEFAULT_ANGULAR_DAMPING)
+        }
+    }
+
+    //Density, friction and restitution are stored in the collider
+    fn same_collider(&self, other: &Self)->bool
+    {
+        self.density == other.density && self.friction == other.friction && self.restitution == other.restitution
+    }
 }
 
-enum PhysicWorldAction
+//Distance joints are not nphysics2d constraints, they are applied by a force generator
+#[derive(Clone)]
+enum JointHandle
 {
-    AddEntity(String,Entity,Isometry<f64>),
+    Constraint(DefaultJointConstraintHandle),
+    Rope(DefaultForceGeneratorHandle)
+}
+
+#[derive(Clone)]
+struct PhysicalEntityJoint
+{
+    entity1: Entity,
+    entity2: Entity,
+    handle: JointHandle
+}
+
+#[derive(Clone,PartialEq,Debug)]
+pub enum PhysicWorldAction
+{
+    ///Layer where the entity has been placed, entity and its position
+    AddEntity(Option<Entity>,Entity,Isometry<f64>),
     RemoveEntity(Entity),
     MoveEntity(Entity,Isometry<f64>),
-    MoveOffsetEntity(Entity,Point)
+    MoveOffsetEntity(Entity,Point),
+
+    SetLinearVelocity(Entity,Vector2<f64>),
+    SetAngularVelocity(Entity,f64),
+    ///Force and world point where it is applied, it lasts for the next step
+    ApplyForce(Entity,Vector2<f64>,Point2<f64>),
+    ///Impulse and world point where it is applied
+    ApplyImpulse(Entity,Vector2<f64>,Point2<f64>),
+
+    ///Joint id, the two connected entities and the joint
+    AddJoint(String,Entity,Entity,PhysicalJoint),
+    RemoveJoint(String),
+
+    ///Force id and the force, one shot forces are added without the id
+    AddForce(String,PhysicalForce),
+    ///The force is removed after the next step
+    AddOneShotForce(PhysicalForce),
+    RemoveForce(String),
+
+    WakeUpEntity(Entity),
+    SleepEntity(Entity),
+
+    ///The physical properties of the entity changed, its body and collider are updated
+    UpdateEntity(Entity)
 }
 
+into_property_source!(PhysicWorldAction);
+
+pub type PhysicWorldActions = Vec<PhysicWorldAction>;
+
+///PhysicWorld where a physical widget has been added
+#[derive(Debug,Default,Clone,Copy,PartialEq)]
+pub struct PhysicWorldEntity(pub Option<Entity>);
+into_property_source!(PhysicWorldEntity);
+
 #[derive(AsAny)]
 pub struct PhysicWorldState {
     actions: Vec<PhysicWorldAction>,
 
     world_size: WorldSize,
+    world_boundary: WorldBoundary,
+    //Static body and colliders of the walls, when the world boundary is made of walls
+    boundary_walls: Option<(DefaultBodyHandle,Vec<DefaultColliderHandle>)>,
+    //Entities outside of the world, so that leaving is notified only once
+    outside_entities: HashSet<Entity>,
 
     physical_entities: HashMap<Entity,PhysicalEntity>,
+    joints: HashMap<String,PhysicalEntityJoint>,
+    forces: HashMap<String,DefaultForceGeneratorHandle>,
+    one_shot_forces: Vec<DefaultForceGeneratorHandle>,
 
     mechanical_world: DefaultMechanicalWorld<f64>,
-    geometrical_world: DefaultGeometricalWorld<f64>,
+    //Geometrical world and colliders, shared with the Battlefield to answer its queries
+    space: PhysicalSpace,
     body_set: DefaultBodySet<f64>,
-    collider_set: DefaultColliderSet<f64>,
     constraint_set: DefaultJointConstraintSet<f64>,
-    force_generator_set: DefaultForceGeneratorSet<f64>
+    force_generator_set: DefaultForceGeneratorSet<f64>,
+
+    //Events produced by the last steps, not yet sent to the Battlefield
+    events: Vec<BattlefieldEvent>,
+    //Last position of the entities moved in the last steps, sent as a single batch of EntityMoved events
+    moved_entities: HashMap<Entity,Isometry<f64>>,
+    movement_epsilon: f64,
+
+    //Real time not yet simulated, consumed by fixed steps
+    accumulator: f64,
+    last_update: Option<Instant>
 }
 
 impl PhysicWorldState
 {
     pub fn action(&mut self,action: PhysicWorldAction) {self.actions.push(action);}
+
+    //Actions sent by the Battlefield are queued on the physic_world_actions property
+    fn fetch_actions(&mut self,ctx: &mut Context)
+    {
+        let actions: Vec<PhysicWorldAction> = ctx.widget().get_mut::<PhysicWorldActions>("physic_world_actions").drain(..).collect();
+        self.actions.extend(actions);
+    }
+
+    pub fn process_actions(&mut self, ctx: &mut Context)
+    {
+        let actions: Vec<PhysicWorldAction> = self.actions.drain(..).collect();
+        for action in actions
+        {
+            match action
+            {
+                PhysicWorldAction::AddEntity(layer,entity,position)=>
+                {
+                    self.add_entity(ctx,layer,entity,position);
+                }
+                PhysicWorldAction::RemoveEntity(entity)=>
+                {
+                    self.remove_entity(entity);
+                }
+                PhysicWorldAction::MoveEntity(entity,position)=>
+                {
+                    self.teleport_entity(ctx,entity,position);
+                }
+                PhysicWorldAction::MoveOffsetEntity(entity,offset)=>
+                {
+                    let position = self.rigid_body_mut(entity).map(|rigid_body|
+                    {
+                        let mut position = rigid_body.position().clone();
+                        position.translation.vector += Vector2::new(offset.x(),offset.y());
+                        position
+                    });
+                    if let Some(position) = position {self.teleport_entity(ctx,entity,position);}
+                }
+                PhysicWorldAction::SetLinearVelocity(entity,velocity)=>
+                {
+                    if let Some(rigid_body) = self.rigid_body_mut(entity)
+                    {
+                        rigid_body.set_linear_velocity(velocity);
+                    }
+                }
+                PhysicWorldAction::SetAngularVelocity(entity,velocity)=>
+                {
+                    if let Some(rigid_body) = self.rigid_body_mut(entity)
+                    {
+                        rigid_body.set_angular_velocity(velocity);
+                    }
+                }
+                PhysicWorldAction::ApplyForce(entity,force,point)=>
+                {
+                    if let Some(rigid_body) = self.rigid_body_mut(entity)
+                    {
+                        rigid_body.apply_force_at_point(0,&force,&point,ForceType::Force,true);
+                    }
+                }
+                PhysicWorldAction::ApplyImpulse(entity,impulse,point)=>
+                {
+                    if let Some(rigid_body) = self.rigid_body_mut(entity)
+                    {
+                        rigid_body.apply_force_at_point(0,&impulse,&point,ForceType::Impulse,true);
+                    }
+                }
+                PhysicWorldAction::AddJoint(id,entity1,entity2,joint)=>
+                {
+                    self.add_joint(id,entity1,entity2,joint);
+                }
+                PhysicWorldAction::RemoveJoint(id)=>
+                {
+                    self.remove_joint(&id);
+                }
+                PhysicWorldAction::AddForce(id,force)=>
+                {
+                    if force.is_one_shot() {self.add_one_shot_force(force);}
+                    else
+                    {
+                        if let Some(handle) = self.forces.remove(&id) {self.force_generator_set.remove(handle);}
+                        let handle = self.force_generator_set.insert(Box::new(force));
+                        self.forces.insert(id,handle);
+                    }
+                }
+                PhysicWorldAction::AddOneShotForce(force)=>
+                {
+                    self.add_one_shot_force(force);
+                }
+                PhysicWorldAction::WakeUpEntity(entity)=>
+                {
+                    if let Some(rigid_body) = self.rigid_body_mut(entity) {rigid_body.activate();}
+                }
+                PhysicWorldAction::SleepEntity(entity)=>
+                {
+                    if let Some(rigid_body) = self.rigid_body_mut(entity) {rigid_body.deactivate();}
+                }
+                PhysicWorldAction::UpdateEntity(entity)=>
+                {
+                    self.update_body(ctx,entity);
+                }
+                PhysicWorldAction::RemoveForce(id)=>
+                {
+                    match self.forces.remove(&id)
+                    {
+                        Some(handle)=>{self.force_generator_set.remove(handle);}
+                        None=>println!("Cannot remove force {}: id not found",id)
+                    }
+                }
+            }
+        }
+    }
+
+    fn rigid_body_mut(&mut self, entity: Entity)->Option<&mut RigidBody<f64>>
+    {
+        match self.physical_entities.get(&entity)
+        {
+            Some(physical_entity)=>self.body_set.rigid_body_mut(physical_entity.rigid_body),
+            None=>{println!("Entity {:#?} is not in the physic world",entity); None}
+        }
+    }
+
+    //Moving an entity is a jump, so it is drawn at the new position without interpolating from the old one.
+    //The widget gets the new position too, otherwise kinematic bodies would be moved back to the old one.
+    fn teleport_entity(&mut self, ctx: &mut Context, entity: Entity, position: Isometry<f64>)
+    {
+        let physical_entity = match self.physical_entities.get_mut(&entity)
+        {
+            Some(physical_entity)=>physical_entity,
+            None=>{println!("Entity {:#?} is not in the physic world",entity); return;}
+        };
+        if let Some(rigid_body) = self.body_set.rigid_body_mut(physical_entity.rigid_body)
+        {
+            rigid_body.set_position(position);
+        }
+        physical_entity.previous_position = position;
+        physical_entity.current_position = position;
+
+        let mut widget = ctx.get_widget(entity);
+        widget.set("physical_position",IsometryF64(position));
+        if widget.has::<IsometryF64>("previous_physical_position")
+        {
+            widget.set("previous_physical_position",IsometryF64(position));
+        }
+    }
+
+    fn step(&mut self)
+    {
+        {
+            let mut space = self.space.borrow_mut();
+            let space = &mut *space;
+            self.mechanical_world.step(
+                &mut space.geometrical_world,
+                &mut self.body_set,
+                &mut space.collider_set,
+                &mut self.constraint_set,
+                &mut self.force_generator_set,
+            );
+        }
+
+        for handle in self.one_shot_forces.drain(..)
+        {
+            self.force_generator_set.remove(handle);
+        }
+
+        for physical_entity in self.physical_entities.values_mut()
+        {
+            if let Some(rigid_body) = self.body_set.rigid_body(physical_entity.rigid_body)
+            {
+                physical_entity.previous_position = physical_entity.current_position;
+                physical_entity.current_position = rigid_body.position().clone();
+            }
+        }
+
+        //Wrapped entities jump after their positions are recorded, so that they are not interpolated across the world
+        self.check_world_boundary();
+        self.check_sleeping_bodies();
+        self.check_moved_bodies();
+
+        let space = self.space.borrow();
+        // Get proximity events
+        for event in space.geometrical_world.proximity_events() {
+            if let Some(event) = self.handle_proximity_event(&space,event) {self.events.push(event);}
+        }
+        // Get contact events
+        for event in space.geometrical_world.contact_events() {
+            if let Some(event) = self.handle_contact_event(&space,event) {self.events.push(event);}
+        }
+    }
+
+    //Walls are rebuilt every time the world size or the boundary mode change
+    fn build_boundary_walls(&mut self)
+    {
+        if let Some((body,colliders)) = self.boundary_walls.take()
+        {
+            for collider in colliders {self.space.borrow_mut().collider_set.remove(collider);}
+            self.body_set.remove(body);
+        }
+        //Without a world size there is no world to close
+        let WorldSize(width,height) = self.world_size;
+        if self.world_boundary != WorldBoundary::Walls || !(width > 0.0 && height > 0.0) {return;}
+
+        let half_thickness = BOUNDARY_THICKNESS / 2.0;
+        let walls = [
+            (Isometry::translation(width / 2.0,-half_thickness),PhysicalShape::cuboid(width / 2.0 + BOUNDARY_THICKNESS,half_thickness)),
+            (Isometry::translation(width / 2.0,height + half_thickness),PhysicalShape::cuboid(width / 2.0 + BOUNDARY_THICKNESS,half_thickness)),
+            (Isometry::translation(-half_thickness,height / 2.0),PhysicalShape::cuboid(half_thickness,height / 2.0 + BOUNDARY_THICKNESS)),
+            (Isometry::translation(width + half_thickness,height / 2.0),PhysicalShape::cuboid(half_thickness,height / 2.0 + BOUNDARY_THICKNESS))
+        ];
+
+        let body = self.body_set.insert(RigidBodyDesc::new().status(BodyStatus::Static).build());
+        let mut colliders = Vec::new();
+        for (position,shape) in walls.iter()
+        {
+            let collider = shape.to_collider_desc()
+            .position(*position)
+            .build(BodyPartHandle(body,0));
+            colliders.push(self.space.borrow_mut().collider_set.insert(collider));
+        }
+        self.boundary_walls = Some((body,colliders));
+    }
+
+    fn check_world_boundary(&mut self)
+    {
+        //Without a world size no entity is considered outside of it
+        let WorldSize(width,height) = self.world_size;
+        if !(width > 0.0 && height > 0.0)
+        {
+            self.outside_entities.clear();
+            return;
+        }
+        for physical_entity in self.physical_entities.values_mut()
+        {
+            let rigid_body = match self.body_set.rigid_body_mut(physical_entity.rigid_body)
+            {
+                Some(rigid_body)=>rigid_body,
+                None=>continue
+            };
+            let mut position = rigid_body.position().clone();
+            let translation = position.translation.vector;
+            let inside = translation.x >= 0.0 && translation.x <= width && translation.y >= 0.0 && translation.y <= height;
+
+            if inside
+            {
+                self.outside_entities.remove(&physical_entity.entity);
+                continue;
+            }
+            if self.outside_entities.insert(physical_entity.entity)
+            {
+                self.events.push(BattlefieldEvent::EntityLeftWorld(physical_entity.entity));
+            }
+
+            if self.world_boundary == WorldBoundary::Wrap
+            {
+                position.translation.vector.x = translation.x.rem_euclid(width);
+                position.translation.vector.y = translation.y.rem_euclid(height);
+                rigid_body.set_position(position);
+                physical_entity.previous_position = position;
+                physical_entity.current_position = position;
+                self.outside_entities.remove(&physical_entity.entity);
+            }
+        }
+    }
+
+    //The new position is compared with the last notified one, so that slow bodies are notified too
+    //once they moved past the epsilon
+    fn check_moved_bodies(&mut self)
+    {
+        for physical_entity in self.physical_entities.values_mut()
+        {
+            let position = match self.body_set.rigid_body(physical_entity.rigid_body)
+            {
+                Some(rigid_body)=>rigid_body.position().clone(),
+                None=>continue
+            };
+            let translation = (position.translation.vector - physical_entity.notified_position.translation.vector).norm();
+            let rotation = physical_entity.notified_position.rotation.angle_to(&position.rotation).abs();
+            if translation <= self.movement_epsilon && rotation <= self.movement_epsilon {continue;}
+
+            physical_entity.notified_position = position;
+            self.moved_entities.insert(physical_entity.entity,position);
+        }
+    }
+
+    //Only dynamic bodies can fall asleep, the others are never simulated
+    fn check_sleeping_bodies(&mut self)
+    {
+        for physical_entity in self.physical_entities.values_mut()
+        {
+            if physical_entity.body_status != PhysicalBodyStatus::Dynamic {continue;}
+            let sleeping = match self.body_set.rigid_body(physical_entity.rigid_body)
+            {
+                Some(rigid_body)=>!rigid_body.is_active(),
+                None=>continue
+            };
+            if sleeping == physical_entity.sleeping {continue;}
+
+            physical_entity.sleeping = sleeping;
+            if sleeping {self.events.push(BattlefieldEvent::EntitySlept(physical_entity.entity));}
+            else {self.events.push(BattlefieldEvent::EntityWoke(physical_entity.entity));}
+        }
+    }
+
+    fn handle_proximity_event(&self, space: &CollisionSpace, event: &ProximityEvent<DefaultColliderHandle>)->Option<BattlefieldEvent>
+    {
+        let entity1 = self.physical_entities.get(&space.entity_of_collider(event.collider1)?)?;
+        let entity2 = self.physical_entities.get(&space.entity_of_collider(event.collider2)?)?;
+
+        let entered = match (event.prev_status,event.new_status)
+        {
+            (Proximity::Intersecting,Proximity::Intersecting)=>return None,
+            (_,Proximity::Intersecting)=>true,
+            (Proximity::Intersecting,_)=>false,
+            _=>return None
+        };
+
+        //When only one of the two is a trigger area, the event names the area and the entity
+        let area = match (&entity1.area_name,&entity2.area_name)
+        {
+            (Some(area_name),None)=>Some((entity1.entity,area_name.clone(),entity2.entity)),
+            (None,Some(area_name))=>Some((entity2.entity,area_name.clone(),entity1.entity)),
+            _=>None
+        };
+
+        match (area,entered)
+        {
+            (Some((area,area_name,entity)),true)=>Some(BattlefieldEvent::AreaEntered(area,area_name,entity)),
+            (Some((area,area_name,entity)),false)=>Some(BattlefieldEvent::AreaLeft(area,area_name,entity)),
+            (None,true)=>Some(BattlefieldEvent::ProximityEntered(entity1.entity,entity2.entity)),
+            (None,false)=>Some(BattlefieldEvent::ProximityLeft(entity1.entity,entity2.entity))
+        }
+    }
+
+    fn handle_contact_event(&self, space: &CollisionSpace, event: &ContactEvent<DefaultColliderHandle>)->Option<BattlefieldEvent>
+    {
+        match event
+        {
+            &ContactEvent::Started(collider1,collider2)=>
+            {
+                Some(BattlefieldEvent::ContactStarted(space.entity_of_collider(collider1)?,space.entity_of_collider(collider2)?))
+            }
+            &ContactEvent::Stopped(collider1,collider2)=>
+            {
+                Some(BattlefieldEvent::ContactStopped(space.entity_of_collider(collider1)?,space.entity_of_collider(collider2)?))
+            }
+        }
+    }
+
+    //Events are appended to the battlefield_events property shared with the Battlefield
+    fn send_events(&mut self, ctx: &mut Context)
+    {
+        for (entity,position) in self.moved_entities.drain()
+        {
+            self.events.push(BattlefieldEvent::EntityMoved(entity,position));
+        }
+        if self.events.is_empty() {return;}
+        let events: Vec<BattlefieldEvent> = self.events.drain(..).collect();
+        ctx.widget().get_mut::<BattlefieldEvents>("battlefield_events").extend(events);
+    }
+
+    //Kinematic bodies are not moved by the simulation, they follow the physical_position of their widget
+    fn follow_kinematic_positions(&mut self, ctx: &mut Context)
+    {
+        for physical_entity in self.physical_entities.values()
+        {
+            if physical_entity.body_status != PhysicalBodyStatus::Kinematic {continue;}
+            if let Some(position) = ctx.get_widget(physical_entity.entity).try_clone::<IsometryF64>("physical_position")
+            {
+                if let Some(rigid_body) = self.body_set.rigid_body_mut(physical_entity.rigid_body)
+                {
+                    rigid_body.set_position(*position);
+                }
+            }
+        }
+    }
+
+    fn sync_positions(&mut self, ctx: &mut Context)
+    {
+        for physical_entity in self.physical_entities.values()
+        {
+            let mut widget = ctx.get_widget(physical_entity.entity);
+            widget.set("physical_position",IsometryF64(physical_entity.current_position));
+            if widget.has::<IsometryF64>("previous_physical_position")
+            {
+                widget.set("previous_physical_position",IsometryF64(physical_entity.previous_position));
+            }
+        }
+    }
+
+    //The camera follows the drawn position of the entity, so that it moves as smoothly as the entity
+    fn follow_camera(&self, ctx: &mut Context, interpolation_factor: f64, steps: f64)
+    {
+        let camera_mode = ctx.widget().clone::<CameraMode>("camera_mode");
+        let entity = match camera_mode
+        {
+            CameraMode::Follow{entity,..}=>entity,
+            CameraMode::Free=>return
+        };
+        let physical_entity = match self.physical_entities.get(&entity)
+        {
+            Some(physical_entity)=>physical_entity,
+            None=>return
+        };
+        let velocity = match self.body_set.rigid_body(physical_entity.rigid_body)
+        {
+            Some(rigid_body)=>rigid_body.velocity().linear,
+            None=>return
+        };
+
+        let position = IsometryF64(physical_entity.previous_position).interpolate(&physical_entity.current_position,interpolation_factor);
+        let camera_center = ctx.widget().clone::<CameraCenter>("camera_center");
+        let (x,y) = camera_mode.follow(
+            (camera_center.0,camera_center.1),
+            (position.translation.vector.x,position.translation.vector.y),
+            (velocity.x,velocity.y),
+            steps
+        );
+        ctx.widget().set("camera_center",CameraCenter(x,y));
+    }
+
+    //The collision groups of the entity inherit the lists that are not set from its layer
+    fn collision_groups(ctx: &mut Context, layer: Option<Entity>, entity: Entity)->PhysicalCollisionGroups
+    {
+        let layer_groups = match layer
+        {
+            Some(layer)=>ctx.get_widget(layer).try_clone::<PhysicalCollisionGroups>("collision_groups").unwrap_or_default(),
+            None=>PhysicalCollisionGroups::default()
+        };
+        ctx.get_widget(entity).try_clone::<PhysicalCollisionGroups>("collision_groups")
+        .unwrap_or_default()
+        .inherit(&layer_groups)
+    }
+
+    //The collider is built from the widget properties, so that it can be rebuilt when they change
+    fn build_collider(ctx: &mut Context, layer: Option<Entity>, entity: Entity, material: &PhysicalMaterial, ccd_enabled: bool, is_sensor: bool, rigid_body: DefaultBodyHandle)->Option<Collider<f64,DefaultBodyHandle>>
+    {
+        let collision_groups = Self::collision_groups(ctx,layer,entity);
+        let physical_shape = ctx.get_widget(entity).try_clone::<PhysicalShape>("physical_shape")?;
+        if physical_shape.is_empty() {println!("Cannot build the collider of entity {:#?}: its compound shape has no parts",entity); return None;}
+
+        //A shape without area would make the mass computation panic: it gets no mass from its density
+        let density = if physical_shape.has_area() {material.density} else {0.0};
+
+        //The entity is stored in the collider, so that queries on the PhysicalSpace can find it
+        let collider_desc = physical_shape.to_collider_desc()
+        .user_data(entity)
+        .sensor(is_sensor)
+        .ccd_enabled(ccd_enabled)
+        .collision_groups(collision_groups.to_collision_groups())
+        .density(density)
+        .material(MaterialHandle::new(BasicMaterial::new(material.restitution,material.friction)));
+        Some(collider_desc.build(BodyPartHandle(rigid_body,0)))
+    }
+
+    fn add_entity(&mut self, ctx: &mut Context, layer: Option<Entity>, entity: Entity, position: Isometry<f64>)
+    {
+        println!("Adding entity");
+        let widget = ctx.get_widget(entity);
+        if !widget.has::<PhysicalShape>("physical_shape") {println!("Entity does not have physical_shape"); return;}
+
+        //Trigger areas are sensors attached to a static body: they detect other entities without touching them
+        let area_name = widget.try_clone::<String16>("area_name").map(|area_name| area_name.to_string());
+        let material = PhysicalMaterial::from_widget(&widget);
+        let ccd_enabled = widget.try_clone::<bool>("ccd_enabled").unwrap_or(false);
+        let sleep_threshold = widget.try_clone::<SleepThreshold>("sleep_threshold").unwrap_or_default();
+
+        let body_status = match area_name
+        {
+            Some(_)=>PhysicalBodyStatus::Static,
+            None=>widget.try_clone::<PhysicalBodyStatus>("body_status").unwrap_or_default()
+        };
+        let rigid_body_desc = RigidBodyDesc::new()
+        .position(position)
+        .status(body_status.to_body_status())
+        .sleep_threshold(sleep_threshold.0)
+        .linear_damping(material.linear_damping)
+        .angular_damping(material.angular_damping);
+        let rigid_body_handle = self.body_set.insert(rigid_body_desc.build());
+
+        let collider = Self::build_collider(ctx,layer,entity,&material,ccd_enabled,area_name.is_some(),rigid_body_handle).unwrap();
+        let collider_handle = self.space.borrow_mut().collider_set.insert(collider);
+
+        //The widget needs to know the physic world to ask for updates when its properties change
+        let mut widget = ctx.get_widget(entity);
+        if widget.has::<PhysicWorldEntity>("physic_world")
+        {
+            widget.set("physic_world",PhysicWorldEntity(Some(ctx.entity)));
+        }
+
+        let physical_entity = PhysicalEntity{entity: entity,layer: layer,collider: collider_handle,rigid_body: rigid_body_handle,area_name: area_name,material: material,ccd_enabled: ccd_enabled,body_status: body_status,sleep_threshold: sleep_threshold,sleeping: false,notified_position: position,previous_position: position,current_position: position};
+        self.physical_entities.insert(entity,physical_entity);
+    }
+
+    //Body status, material and ccd properties can be changed at any time, the widget asks for an update when they change.
+    //They are compared with the ones applied to the body: damping is changed on the rigid body,
+    //while the collider is rebuilt for density, friction, restitution and ccd.
+    fn update_body(&mut self, ctx: &mut Context, entity: Entity)
+    {
+        //The entity may have been removed after asking for the update
+        let physical_entity = match self.physical_entities.get(&entity)
+        {
+            Some(physical_entity)=>physical_entity.clone(),
+            None=>return
+        };
+        let widget = ctx.get_widget(entity);
+        let material = PhysicalMaterial::from_widget(&widget);
+        let body_status = widget.try_clone::<PhysicalBodyStatus>("body_status");
+        let ccd_enabled = widget.try_clone::<bool>("ccd_enabled").unwrap_or(false);
+        let sleep_threshold = widget.try_clone::<SleepThreshold>("sleep_threshold").unwrap_or_default();
+
+        if sleep_threshold != physical_entity.sleep_threshold
+        {
+            if let Some(rigid_body) = self.body_set.rigid_body_mut(physical_entity.rigid_body)
+            {
+                rigid_body.set_deactivation_threshold(sleep_threshold.0);
+            }
+            self.physical_entities.get_mut(&entity).unwrap().sleep_threshold = sleep_threshold;
+        }
+
+        //Trigger areas are always static
+        if let (Some(body_status),None) = (body_status,&physical_entity.area_name)
+        {
+            if body_status != physical_entity.body_status
+            {
+                if let Some(rigid_body) = self.body_set.rigid_body_mut(physical_entity.rigid_body)
+                {
+                    rigid_body.set_status(body_status.to_body_status());
+                }
+                self.physical_entities.get_mut(&entity).unwrap().body_status = body_status;
+            }
+        }
+
+        if material == physical_entity.material && ccd_enabled == physical_entity.ccd_enabled {return;}
+
+        if let Some(rigid_body) = self.body_set.rigid_body_mut(physical_entity.rigid_body)
+        {
+            rigid_body.set_linear_damping(material.linear_damping);
+            rigid_body.set_angular_damping(material.angular_damping);
+        }
+
+        let mut collider_handle = physical_entity.collider;
+        if !material.same_collider(&physical_entity.material) || ccd_enabled != physical_entity.ccd_enabled
+        {
+            if let Some(collider) = Self::build_collider(ctx,physical_entity.layer,entity,&material,ccd_enabled,physical_entity.area_name.is_some(),physical_entity.rigid_body)
+            {
+                let mut space = self.space.borrow_mut();
+                space.collider_set.remove(physical_entity.collider);
+                collider_handle = space.collider_set.insert(collider);
+            }
+        }
+
+        let physical_entity = self.physical_entities.get_mut(&entity).unwrap();
+        physical_entity.collider = collider_handle;
+        physical_entity.material = material;
+        physical_entity.ccd_enabled = ccd_enabled;
+    }
+
+    fn add_one_shot_force(&mut self, force: PhysicalForce)
+    {
+        let handle = self.force_generator_set.insert(Box::new(force));
+        self.one_shot_forces.push(handle);
+    }
+
+    fn add_joint(&mut self, id: String, entity1: Entity, entity2: Entity, joint: PhysicalJoint)
+    {
+        let (body1,body2) = match (self.physical_entities.get(&entity1),self.physical_entities.get(&entity2))
+        {
+            (Some(physical_entity1),Some(physical_entity2))=>(physical_entity1.rigid_body,physical_entity2.rigid_body),
+            _=>{println!("Cannot add joint {}: entities are not in the physic world",id); return;}
+        };
+        if self.joints.contains_key(&id) {self.remove_joint(&id);}
+
+        let handle = match joint.insert_constraint(body1,body2,&mut self.constraint_set)
+        {
+            Some(handle)=>JointHandle::Constraint(handle),
+            None=>
+            {
+                let rope = joint.to_force_generator(body1,body2).unwrap();
+                JointHandle::Rope(self.force_generator_set.insert(Box::new(rope)))
+            }
+        };
+        self.joints.insert(id,PhysicalEntityJoint{entity1: entity1,entity2: entity2,handle: handle});
+    }
+
+    fn remove_joint(&mut self, id: &str)
+    {
+        match self.joints.remove(id)
+        {
+            Some(joint)=>match joint.handle
+            {
+                JointHandle::Constraint(handle)=>{self.constraint_set.remove(handle);}
+                JointHandle::Rope(handle)=>{self.force_generator_set.remove(handle);}
+            }
+            None=>println!("Cannot remove joint {}: id not found",id)
+        }
+    }
+
+    fn remove_entity(&mut self,entity: Entity)
+    {
+        //Joints cannot exist without both of their entities
+        let joints: Vec<String> = self.joints.iter()
+        .filter(|(_,joint)| joint.entity1 == entity || joint.entity2 == entity)
+        .map(|(id,_)| id.clone())
+        .collect();
+        for id in joints {self.remove_joint(&id);}
+
+        self.outside_entities.remove(&entity);
+        self.moved_entities.remove(&entity);
+        if let Some(physical_entity) = self.physical_entities.remove(&entity)
+        {
+            self.space.borrow_mut().collider_set.remove(physical_entity.collider);
+            self.body_set.remove(physical_entity.rigid_body);
+        }
+        //Entities without a physical shape are never added, so nothing has to be removed for them
+    }
 }
 
 impl Default for PhysicWorldState
@@ -64,15 +816,27 @@ impl Default for PhysicWorldState
         {
             actions: Vec::new(),
             world_size: WorldSize(200.0,200.0),
+            world_boundary: WorldBoundary::default(),
+            boundary_walls: None,
+            outside_entities: HashSet::new(),
 
             physical_entities: HashMap::new(),
+            joints: HashMap::new(),
+            forces: HashMap::new(),
+            one_shot_forces: Vec::new(),
 
-            mechanical_world: DefaultMechanicalWorld::new(Vector2::new(0.0, -9.81)),
-            geometrical_world: DefaultGeometricalWorld::new(),
+            mechanical_world: DefaultMechanicalWorld::new(BattlefieldView::default().gravity()),
+            space: PhysicalSpace::default(),
             body_set: DefaultBodySet::new(),
-            collider_set: DefaultColliderSet::new(),
             constraint_set: DefaultJointConstraintSet::new(),
-            force_generator_set: DefaultForceGeneratorSet::new()
+            force_generator_set: DefaultForceGeneratorSet::new(),
+
+            events: Vec::new(),
+            moved_entities: HashMap::new(),
+            movement_epsilon: DEFAULT_MOVEMENT_EPSILON,
+
+            accumulator: 0.0,
+            last_update: None
         }
     }
 }
@@ -80,86 +844,78 @@ impl Default for PhysicWorldState
 impl State for PhysicWorldState {
     fn init(&mut self, registry: &mut Registry, ctx: &mut Context)
     {
-
+        self.space = ctx.widget().clone::<PhysicalSpace>("physical_space");
+        self.last_update = Some(Instant::now());
     }
     fn update(&mut self, _: &mut Registry, ctx: &mut Context)
     {
-        let mut update_world = false;
+        self.fetch_actions(ctx);
+        self.process_actions(ctx);
+        self.follow_kinematic_positions(ctx);
+
+        //A timestep that is not positive or max_substeps set to 0 pause the simulation
+        let timestep = *ctx.widget().get::<f64>("timestep");
+        let max_substeps = *ctx.widget().get::<u32>("max_substeps");
+        let valid_timestep = timestep > 0.0 && timestep.is_finite();
+        let paused = !valid_timestep || max_substeps == 0;
+        if valid_timestep && timestep != self.mechanical_world.timestep() {self.mechanical_world.set_timestep(timestep);}
+
+        self.movement_epsilon = *ctx.widget().get::<f64>("movement_epsilon");
+
+        let world_size = ctx.widget().clone::<WorldSize>("world_size");
+        let world_boundary = ctx.widget().clone::<WorldBoundary>("world_boundary");
+        if world_size != self.world_size || world_boundary != self.world_boundary
+        {
+            self.world_size = world_size;
+            self.world_boundary = world_boundary;
+            self.build_boundary_walls();
+        }
 
-        //let mut physical_events = Vec::new();
+        ctx.widget().clone::<CcdConfiguration>("ccd_configuration").apply(&mut self.mechanical_world.integration_parameters);
 
-        let actions: Vec<PhysicWorldAction> = self.actions.drain(..).collect();
-        for action in actions
+        let battlefield_view = ctx.widget().clone::<BattlefieldView>("battlefield_view");
+        let gravity = ctx.widget().clone::<Gravity>("gravity").vector(&battlefield_view);
+        if gravity != self.mechanical_world.gravity {self.mechanical_world.gravity = gravity;}
+
+        //The simulation advances by fixed steps, consuming the real time elapsed since the last update
+        let now = Instant::now();
+        let elapsed = match self.last_update
         {
-            match action
-            {
-                PhysicWorldAction::AddEntity(layer_name,entity,position)=>
-                {
-                    println!("Adding entity");
-                    let widget = ctx.get_widget(entity);
-                    let physical_shape = match widget.try_clone::<PhysicalShape>("physical_shape")
-                    {
-                        Some(physical_shape)=>
-                        {
-                            println!("Entity have physical_shape");
-                            let rigid_body = RigidBodyDesc::new().set_position(position).build();
-                            let rigid_body_handle = self.body_set.insert(rigid_body);
-                            let collider = physical_shape.to_collider(rigid_body_handle.clone());
-                            let collider_handle = self.collider_set.insert(collider);
-
-                            let physical_entity = PhysicalEntity{entity: entity,collider: collider_handle,rigid_body: rigid_body_handle};
-                            self.physical_entities.insert(entity,physical_entity);
-                        }
-                        None=>println!("Entity does not have physical_shape")
-                    };
-                    update_world = true;
-                }
-                PhysicWorldAction::MoveEntity(entity,position)=>
-                {
-                    match self.physical_entities.get(&entity)
-                    {
-                        Some(physical_entity)=>
-                        {
-                            let rigid_body = self.body_set.rigid_body_mut(physical_entity.rigid_body).unwrap();
-                            rigid_body.set_position(position);
-                        }
-                        None=>()
-                    }
-                    update_world = true;
-                }
-                _=>{}
-            }
+            Some(last_update)=>now.duration_since(last_update).as_secs_f64(),
+            None=>0.0
+        };
+        self.last_update = Some(now);
 
-            if update_world
+        //While paused the time is not accumulated, so that the simulation does not catch up when it resumes
+        let interpolation_factor = if paused
+        {
+            self.accumulator = 0.0;
+            1.0
+        }
+        else
+        {
+            self.accumulator += elapsed;
+
+            let mut substeps = 0;
+            while self.accumulator >= timestep && substeps < max_substeps
             {
-                self.mechanical_world.step(
-                    &mut self.geometrical_world,
-                    &mut self.body_set,
-                    &mut self.collider_set,
-                    &mut self.constraint_set,
-                    &mut self.force_generator_set,
-                );
+                self.step();
+                self.accumulator -= timestep;
+                substeps += 1;
+            }
 
-                // Get proximity events
-                for event in self.geometrical_world.proximity_events() {
-                    handle_proximity_event(&self.mechanical_world, event)
-                }
-                // Get contact events
-                for event in self.geometrical_world.contact_events() {
-                    handle_contact_event(&self.mechanical_world, event)
-                }
+            //If the simulation cannot keep up, the remaining time is dropped instead of piling up
+            if substeps == max_substeps && self.accumulator >= timestep {self.accumulator = 0.0;}
 
-                // Get physical events
-                for physical_entity in self.physical_entities.values()
-                {
-                    //let current_isometry = ctx.get_widget(physical_entity.entity).clone::<IsometryF64>("physical_position");
-                    let new_isometry = self.body_set.rigid_body(physical_entity.rigid_body).unwrap().position().clone();
-                    ctx.get_widget(physical_entity.entity).set("physical_position",new_isometry);
-                }
+            if substeps > 0 {self.sync_positions(ctx);}
 
-                update_world = false;
-            }
-        }
+            //Fraction of a step elapsed since the last one, used by the CameraLayout to interpolate the positions
+            (self.accumulator / timestep).min(1.0)
+        };
+        ctx.widget().set("interpolation_factor",interpolation_factor);
+        let camera_steps = elapsed / if valid_timestep {timestep} else {DEFAULT_TIMESTEP};
+        self.follow_camera(ctx,interpolation_factor,camera_steps);
+        self.send_events(ctx);
     }
 }
 
@@ -172,7 +928,29 @@ widget!(
     */
     PhysicWorld<PhysicWorldState>
     {
-        world_size: WorldSize
+        world_size: WorldSize,
+        physic_world_actions: PhysicWorldActions,
+
+        ///Length in seconds of a single simulation step, the simulation is paused when it is not positive
+        timestep: f64,
+        ///Maximum number of steps simulated in a single update, the simulation is paused when it is 0
+        max_substeps: u32,
+
+        battlefield_view: BattlefieldView,
+        gravity: Gravity,
+        world_boundary: WorldBoundary,
+        ccd_configuration: CcdConfiguration,
+        ///Minimum translation or rotation of a body to emit an EntityMoved event
+        movement_epsilon: f64,
+        ///Fraction of a step elapsed since the last simulated one
+        interpolation_factor: f64,
+        ///Colliders shared with the Battlefield for its queries
+        physical_space: PhysicalSpace,
+        ///Moved when the camera_mode follows an entity
+        camera_center: CameraCenter,
+        camera_mode: CameraMode,
+
+        battlefield_events: BattlefieldEvents
     }
 );
 
@@ -180,60 +958,8 @@ impl Template for PhysicWorld {
     fn template(self, id: Entity, ctx: &mut BuildContext) -> Self {
         self.name("PhysicWorld")
         .world_size(WorldSize(200.0,200.0))
-    }
-}
-
-
-
-fn handle_proximity_event(
-    world: &DefaultMechanicalWorld<f64>,
-    event: &ProximityEvent<DefaultBodyHandle>,
-) {
-    println!("Proximity detected");
-    /*
-    // The collision object with a None velocity is the coloured area.
-    let area_name;
-    let co1 = world.collision_object(event.collider1).unwrap();
-    let co2 = world.collision_object(event.collider2).unwrap();
-
-    if co1.data().velocity.is_none() {
-        area_name = co1.data().name;
-    } else {
-        area_name = co2.data().name;
-    }
-
-    if event.new_status == Proximity::Intersecting {
-        println!("The ball enters the {} area.", area_name);
-    } else if event.new_status == Proximity::Disjoint {
-        println!("The ball leaves the {} area.", area_name);
-    }
-    */
-}
-
-fn handle_contact_event(
-    world: &DefaultMechanicalWorld<f64>,
-    event: &ContactEvent<DefaultBodyHandle>)
-{
-    println!("Contact detected");
-    if let &ContactEvent::Started(collider1, collider2) = event {
-        /*
-        // NOTE: real-life applications would avoid this systematic allocation.
-        let pair = world.contact_pair(collider1, collider2).unwrap();
-        let mut collector = Vec::new();
-        pair.contacts(&mut collector);
-
-        let co1 = world.collision_object(collider1).unwrap();
-        let co2 = world.collision_object(collider2).unwrap();
-
-        // The ball is the one with a non-None velocity.
-        if let Some(ref vel) = co1.data().velocity {
-            let normal = collector[0].deepest_contact().unwrap().contact.normal;
-            vel.set(vel.get() - 2.0 * na::dot(&vel.get(), &normal) * *normal);
-        }
-        if let Some(ref vel) = co2.data().velocity {
-            let normal = -collector[0].deepest_contact().unwrap().contact.normal;
-            vel.set(vel.get() - 2.0 * na::dot(&vel.get(), &normal) * *normal);
-        }
-        */
+        .timestep(DEFAULT_TIMESTEP)
+        .max_substeps(DEFAULT_MAX_SUBSTEPS)
+        .movement_epsilon(DEFAULT_MOVEMENT_EPSILON)
     }
 }
@@ -0,0 +1,97 @@
+use nphysics2d::object::{BodySet,BodyStatus,DefaultBodyHandle};
+use nphysics2d::force_generator::ForceGenerator;
+use nphysics2d::solver::IntegrationParameters;
+use nphysics2d::algebra::ForceType;
+use nphysics2d::math::{Vector,Point};
+
+///Force applied by the physic world to every dynamic body in its area of effect.
+#[derive(Debug,Clone,PartialEq)]
+pub enum PhysicalForce
+{
+    ///Constant force applied to the bodies whose center of mass is inside the rectangle from min to max
+    Wind
+    {
+        min: Point<f64>,
+        max: Point<f64>,
+        force: Vector<f64>
+    },
+    ///Force toward the center, decreasing with the distance until it is zero at radius.
+    ///A positive strength attracts the bodies, a negative one repels them.
+    Radial
+    {
+        center: Point<f64>,
+        radius: f64,
+        strength: f64
+    },
+    ///Impulse away from the center applied only once, decreasing with the distance until it is zero at radius
+    Explosion
+    {
+        center: Point<f64>,
+        radius: f64,
+        impulse: f64
+    }
+}
+
+impl PhysicalForce
+{
+    pub fn is_one_shot(&self)->bool
+    {
+        match self
+        {
+            Self::Explosion{..}=>true,
+            _=>false
+        }
+    }
+
+    //Force applied to a body with the center of mass in the given point, with the kind of force
+    fn force_at(&self, point: &Point<f64>)->Option<(Vector<f64>,ForceType)>
+    {
+        match self
+        {
+            Self::Wind{min,max,force}=>
+            {
+                let inside = point.x >= min.x && point.x <= max.x && point.y >= min.y && point.y <= max.y;
+                if inside {Some((*force,ForceType::Force))} else {None}
+            }
+            Self::Radial{center,radius,strength}=>
+            {
+                let (direction,falloff) = falloff(center,point,*radius)?;
+                Some((-direction * strength * falloff,ForceType::Force))
+            }
+            Self::Explosion{center,radius,impulse}=>
+            {
+                let (direction,falloff) = falloff(center,point,*radius)?;
+                Some((direction * impulse * falloff,ForceType::Impulse))
+            }
+        }
+    }
+}
+
+//Direction from the center to the point and linear falloff, None outside the radius
+fn falloff(center: &Point<f64>, point: &Point<f64>, radius: f64)->Option<(Vector<f64>,f64)>
+{
+    let distance = (point - center).norm();
+    if distance >= radius || distance == 0.0 {return None;}
+    Some(((point - center) / distance,1.0 - distance / radius))
+}
+
+impl ForceGenerator<f64,DefaultBodyHandle> for PhysicalForce
+{
+    fn apply(&mut self, _: &IntegrationParameters<f64>, bodies: &mut dyn BodySet<f64,Handle = DefaultBodyHandle>)
+    {
+        let force = &*self;
+        bodies.foreach_mut(&mut |_, body|
+        {
+            if body.status() != BodyStatus::Dynamic {return;}
+            let center_of_mass = match body.part(0)
+            {
+                Some(part)=>part.center_of_mass(),
+                None=>return
+            };
+            if let Some((vector,force_type)) = force.force_at(&center_of_mass)
+            {
+                body.apply_force_at_point(0,&vector,&center_of_mass,force_type,true);
+            }
+        });
+    }
+}
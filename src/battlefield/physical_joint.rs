@@ -0,0 +1,175 @@
+use nphysics2d::object::{BodySet,BodyStatus,DefaultBodyHandle,BodyPartHandle};
+use nphysics2d::joint::{RevoluteConstraint,PrismaticConstraint,FixedConstraint,DefaultJointConstraintSet,DefaultJointConstraintHandle};
+use nphysics2d::force_generator::ForceGenerator;
+use nphysics2d::solver::IntegrationParameters;
+use nphysics2d::algebra::ForceType;
+use nphysics2d::math::{Isometry,Vector,Point};
+
+use na::Unit;
+
+///Fraction of the rope stretching that is corrected at every step
+const ROPE_CORRECTION: f64 = 0.2;
+
+///Motor of a revolute or prismatic joint.
+///For a revolute joint the velocity is angular and max_force is a torque.
+#[derive(Debug,Clone,PartialEq)]
+pub struct JointMotor
+{
+    pub velocity: f64,
+    pub max_force: f64
+}
+
+///Joint between two physical entities, anchors are in the local space of each body.
+#[derive(Debug,Clone,PartialEq)]
+pub enum PhysicalJoint
+{
+    ///The two bodies rotate around the anchors, limits are the minimum and maximum angle
+    Revolute
+    {
+        anchor1: Point<f64>,
+        anchor2: Point<f64>,
+        limits: Option<(f64,f64)>,
+        motor: Option<JointMotor>
+    },
+    ///The second body slides along the axis of the first one, limits are the minimum and maximum offset
+    Prismatic
+    {
+        anchor1: Point<f64>,
+        axis1: Vector<f64>,
+        anchor2: Point<f64>,
+        limits: Option<(f64,f64)>,
+        motor: Option<JointMotor>
+    },
+    ///The two bodies move as a single one
+    Fixed
+    {
+        anchor1: Isometry<f64>,
+        anchor2: Isometry<f64>
+    },
+    ///The anchors cannot be more distant than max_length, like a rope
+    Distance
+    {
+        anchor1: Point<f64>,
+        anchor2: Point<f64>,
+        max_length: f64
+    }
+}
+
+impl PhysicalJoint
+{
+    ///Every joint except Distance is solved as an nphysics2d joint constraint
+    pub fn insert_constraint(&self, body1: DefaultBodyHandle, body2: DefaultBodyHandle, constraint_set: &mut DefaultJointConstraintSet<f64>)->Option<DefaultJointConstraintHandle>
+    {
+        let part1 = BodyPartHandle(body1,0);
+        let part2 = BodyPartHandle(body2,0);
+        match self
+        {
+            Self::Revolute{anchor1,anchor2,limits,motor}=>
+            {
+                let mut constraint = RevoluteConstraint::new(part1,part2,*anchor1,*anchor2);
+                if let Some((min,max)) = limits
+                {
+                    constraint.enable_min_angle(*min);
+                    constraint.enable_max_angle(*max);
+                }
+                if let Some(motor) = motor
+                {
+                    constraint.enable_angular_motor();
+                    constraint.set_desired_angular_motor_velocity(motor.velocity);
+                    constraint.set_max_angular_motor_torque(motor.max_force);
+                }
+                Some(constraint_set.insert(constraint))
+            }
+            Self::Prismatic{anchor1,axis1,anchor2,limits,motor}=>
+            {
+                let mut constraint = PrismaticConstraint::new(part1,part2,*anchor1,Unit::new_normalize(*axis1),*anchor2);
+                if let Some((min,max)) = limits
+                {
+                    constraint.enable_min_offset(*min);
+                    constraint.enable_max_offset(*max);
+                }
+                if let Some(motor) = motor
+                {
+                    constraint.enable_linear_motor();
+                    constraint.set_desired_linear_motor_velocity(motor.velocity);
+                    constraint.set_max_linear_motor_force(motor.max_force);
+                }
+                Some(constraint_set.insert(constraint))
+            }
+            Self::Fixed{anchor1,anchor2}=>
+            {
+                let constraint = FixedConstraint::new(
+                    part1,
+                    part2,
+                    Point::from(anchor1.translation.vector),
+                    anchor1.rotation,
+                    Point::from(anchor2.translation.vector),
+                    anchor2.rotation
+                );
+                Some(constraint_set.insert(constraint))
+            }
+            Self::Distance{..}=>None
+        }
+    }
+
+    pub fn to_force_generator(&self, body1: DefaultBodyHandle, body2: DefaultBodyHandle)->Option<RopeGenerator>
+    {
+        match self
+        {
+            Self::Distance{anchor1,anchor2,max_length}=>
+            {
+                Some(RopeGenerator{body1: body1,anchor1: *anchor1,body2: body2,anchor2: *anchor2,max_length: *max_length})
+            }
+            _=>None
+        }
+    }
+}
+
+///Keeps two anchors within max_length, changing the velocity of the bodies when the rope is stretched.
+pub struct RopeGenerator
+{
+    body1: DefaultBodyHandle,
+    anchor1: Point<f64>,
+    body2: DefaultBodyHandle,
+    anchor2: Point<f64>,
+    max_length: f64
+}
+
+//Position, linear velocity and inverse mass of the first part of a body
+fn body_state(bodies: &dyn BodySet<f64,Handle = DefaultBodyHandle>, handle: DefaultBodyHandle)->Option<(Isometry<f64>,Vector<f64>,f64)>
+{
+    let body = bodies.get(handle)?;
+    let part = body.part(0)?;
+    let mass = part.inertia().linear;
+    let inverse_mass = if body.status() == BodyStatus::Dynamic && mass > 0.0 {1.0 / mass} else {0.0};
+    Some((part.position(),part.velocity().linear,inverse_mass))
+}
+
+impl ForceGenerator<f64,DefaultBodyHandle> for RopeGenerator
+{
+    fn apply(&mut self, parameters: &IntegrationParameters<f64>, bodies: &mut dyn BodySet<f64,Handle = DefaultBodyHandle>)
+    {
+        let (position1,velocity1,inverse_mass1) = match body_state(&*bodies,self.body1) {Some(state)=>state,None=>return};
+        let (position2,velocity2,inverse_mass2) = match body_state(&*bodies,self.body2) {Some(state)=>state,None=>return};
+
+        let anchor1 = position1 * self.anchor1;
+        let anchor2 = position2 * self.anchor2;
+        let distance = (anchor2 - anchor1).norm();
+        let inverse_mass = inverse_mass1 + inverse_mass2;
+        if distance <= self.max_length || inverse_mass == 0.0 {return;}
+
+        //The separating velocity is removed and part of the stretching is corrected
+        let direction = (anchor2 - anchor1) / distance;
+        let separating_velocity = (velocity2 - velocity1).dot(&direction).max(0.0);
+        let velocity_change = separating_velocity + ROPE_CORRECTION * (distance - self.max_length) / parameters.dt();
+
+        if let Some(body1) = bodies.get_mut(self.body1)
+        {
+            body1.apply_force_at_point(0,&(direction * velocity_change * inverse_mass1 / inverse_mass),&anchor1,ForceType::VelocityChange,true);
+        }
+        if let Some(body2) = bodies.get_mut(self.body2)
+        {
+            body2.apply_force_at_point(0,&(-direction * velocity_change * inverse_mass2 / inverse_mass),&anchor2,ForceType::VelocityChange,true);
+        }
+    }
+}
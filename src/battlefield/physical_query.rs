@@ -0,0 +1,189 @@
+use orbtk::prelude::*;
+
+use ncollide2d::query::{self,Proximity,Ray};
+use ncollide2d::bounding_volume::BoundingVolume;
+use nphysics2d::object::{DefaultColliderSet,DefaultColliderHandle,DefaultBodyHandle,Collider};
+use nphysics2d::world::DefaultGeometricalWorld;
+use nphysics2d::math::{Isometry,Vector,Point};
+
+use std::rc::Rc;
+use std::cell::{RefCell,Ref,RefMut};
+use std::fmt;
+use std::cmp::Ordering;
+
+use super::{PhysicalShape,PhysicalCollisionGroups};
+
+///Entity hit by a ray or shape cast, with the hit point and normal in world coordinates
+///and the distance travelled from the origin.
+#[derive(Debug,Clone,PartialEq)]
+pub struct QueryHit
+{
+    pub entity: Entity,
+    pub point: Point<f64>,
+    pub normal: Vector<f64>,
+    pub distance: f64
+}
+
+///Geometrical world and colliders of the physic world.
+///Only the colliders that can interact with the groups of a query are considered, trigger areas are ignored.
+pub struct CollisionSpace
+{
+    pub geometrical_world: DefaultGeometricalWorld<f64>,
+    pub collider_set: DefaultColliderSet<f64>
+}
+
+impl CollisionSpace
+{
+    ///The entity of a collider, found in the collider itself. Colliders that are not entities, as the world walls, have none.
+    pub fn entity_of_collider(&self, handle: DefaultColliderHandle)->Option<Entity>
+    {
+        entity_of_collider(self.collider_set.get(handle)?)
+    }
+}
+
+///Shared handle to the CollisionSpace of the physic world, so that the Battlefield can query it at any time.
+///The PhysicWorld gets it from the physical_space property shared with the Battlefield.
+#[derive(Clone)]
+pub struct PhysicalSpace(Rc<RefCell<CollisionSpace>>);
+
+impl Default for PhysicalSpace
+{
+    fn default()->Self
+    {
+        Self(Rc::new(RefCell::new(CollisionSpace
+        {
+            geometrical_world: DefaultGeometricalWorld::new(),
+            collider_set: DefaultColliderSet::new()
+        })))
+    }
+}
+
+impl PartialEq for PhysicalSpace
+{
+    fn eq(&self, other: &Self)->bool {Rc::ptr_eq(&self.0,&other.0)}
+}
+
+impl fmt::Debug for PhysicalSpace
+{
+    fn fmt(&self, formatter: &mut fmt::Formatter)->fmt::Result
+    {
+        write!(formatter,"PhysicalSpace")
+    }
+}
+into_property_source!(PhysicalSpace);
+
+impl PhysicalSpace
+{
+    pub fn borrow(&self)->Ref<CollisionSpace> {self.0.borrow()}
+    pub fn borrow_mut(&self)->RefMut<CollisionSpace> {self.0.borrow_mut()}
+
+    ///Entities hit by a ray, sorted by distance
+    pub fn cast_ray(&self, origin: &Point<f64>, direction: &Vector<f64>, max_distance: f64, groups: &PhysicalCollisionGroups)->Vec<QueryHit>
+    {
+        //Without a direction nothing can be hit
+        if direction.norm() == 0.0 {return Vec::new();}
+        //With a normalized direction the time of impact is the distance
+        let ray = Ray::new(*origin,direction.normalize());
+        let space = self.borrow();
+        let mut hits: Vec<QueryHit> = space.geometrical_world
+        .interferences_with_ray(&space.collider_set,&ray,max_distance,&groups.to_collision_groups())
+        .filter(|(_,collider,_)| !collider.is_sensor())
+        .filter_map(|(_,collider,intersection)|
+        {
+            Some(QueryHit
+            {
+                entity: entity_of_collider(collider)?,
+                point: ray.point_at(intersection.toi),
+                normal: intersection.normal,
+                distance: intersection.toi
+            })
+        })
+        .collect();
+        hits.sort_by(|hit1,hit2| hit1.distance.partial_cmp(&hit2.distance).unwrap_or(Ordering::Equal));
+        hits
+    }
+
+    ///Entities hit by a shape moving from origin along the direction, sorted by distance
+    pub fn cast_shape(&self, shape: &PhysicalShape, origin: &Isometry<f64>, direction: &Vector<f64>, max_distance: f64, groups: &PhysicalCollisionGroups)->Vec<QueryHit>
+    {
+        if shape.is_empty() {return Vec::new();}
+        let shape = shape.to_shape_handle();
+        if direction.norm() == 0.0 {return Vec::new();}
+        let velocity = direction.normalize();
+        let mut destination = origin.clone();
+        destination.translation.vector += velocity * max_distance;
+
+        //The broad phase is queried with the box that contains the whole movement
+        let swept_aabb = shape.aabb(origin).merged(&shape.aabb(&destination));
+        let space = self.borrow();
+        let mut hits: Vec<QueryHit> = space.geometrical_world
+        .interferences_with_aabb(&space.collider_set,&swept_aabb,&groups.to_collision_groups())
+        .filter(|(_,collider)| !collider.is_sensor())
+        .filter_map(|(_,collider)|
+        {
+            let toi = query::time_of_impact(
+                origin,&velocity,&*shape,
+                collider.position(),&Vector::zeros(),collider.shape(),
+                max_distance,0.0
+            )?;
+            Some(QueryHit
+            {
+                entity: entity_of_collider(collider)?,
+                point: collider.position() * toi.witness2,
+                normal: collider.position() * toi.normal2.into_inner(),
+                distance: toi.toi
+            })
+        })
+        .collect();
+        hits.sort_by(|hit1,hit2| hit1.distance.partial_cmp(&hit2.distance).unwrap_or(Ordering::Equal));
+        hits
+    }
+
+    ///Entities that contain the point
+    pub fn entities_at_point(&self, point: &Point<f64>, groups: &PhysicalCollisionGroups)->Vec<Entity>
+    {
+        let space = self.borrow();
+        let entities: Vec<Entity> = space.geometrical_world
+        .interferences_with_point(&space.collider_set,point,&groups.to_collision_groups())
+        .filter(|(_,collider)| !collider.is_sensor())
+        .filter_map(|(_,collider)| entity_of_collider(collider))
+        .collect();
+        entities
+    }
+
+    ///Entities that intersect the shape placed at position.
+    ///The broad phase finds the candidates, then the exact shapes are tested.
+    pub fn entities_in_shape(&self, shape: &PhysicalShape, position: &Isometry<f64>, groups: &PhysicalCollisionGroups)->Vec<Entity>
+    {
+        if shape.is_empty() {return Vec::new();}
+        let shape = shape.to_shape_handle();
+        let space = self.borrow();
+        let entities: Vec<Entity> = space.geometrical_world
+        .interferences_with_aabb(&space.collider_set,&shape.aabb(position),&groups.to_collision_groups())
+        .filter(|(_,collider)| !collider.is_sensor())
+        .filter(|(_,collider)| query::proximity(position,&*shape,collider.position(),collider.shape(),0.0) == Proximity::Intersecting)
+        .filter_map(|(_,collider)| entity_of_collider(collider))
+        .collect();
+        entities
+    }
+
+    ///Entities that intersect the circle
+    pub fn entities_in_circle(&self, center: &Point<f64>, radius: f64, groups: &PhysicalCollisionGroups)->Vec<Entity>
+    {
+        self.entities_in_shape(&PhysicalShape::ball(radius),&Isometry::translation(center.x,center.y),groups)
+    }
+
+    ///Entities that intersect the rectangle from min to max
+    pub fn entities_in_rectangle(&self, min: &Point<f64>, max: &Point<f64>, groups: &PhysicalCollisionGroups)->Vec<Entity>
+    {
+        let center = na::center(min,max);
+        let shape = PhysicalShape::cuboid((max.x - min.x).abs() / 2.0,(max.y - min.y).abs() / 2.0);
+        self.entities_in_shape(&shape,&Isometry::translation(center.x,center.y),groups)
+    }
+}
+
+//Colliders of the physical entities store their entity, the other ones, as the world walls, have none
+fn entity_of_collider(collider: &Collider<f64,DefaultBodyHandle>)->Option<Entity>
+{
+    collider.user_data()?.downcast_ref::<Entity>().cloned()
+}
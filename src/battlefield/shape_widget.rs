@@ -2,15 +2,25 @@ use orbtk::{prelude::*, render::concurrent::RenderContext2D};
 
 use super::PhysicalShape;
 use super::IsometryF64;
+use super::PhysicalCollisionGroups;
+use super::PhysicalBodyStatus;
+use super::SleepThreshold;
+use super::physic_world::{DEFAULT_DENSITY,DEFAULT_FRICTION,DEFAULT_RESTITUTION,DEFAULT_LINEAR_DAMPING,DEFAULT_ANGULAR_DAMPING};
+use super::physic_world::{PhysicWorldAction,PhysicWorldActions,PhysicWorldEntity};
+
+enum ShapeWidgetAction
+{
+    PhysicalPropertiesChanged
+}
 
 #[derive(Default,AsAny)]
 pub struct ShapeWidgetState {
-
+    actions: Vec<ShapeWidgetAction>
 }
 
 impl ShapeWidgetState
 {
-
+    pub fn action(&mut self,action: ShapeWidgetAction) {self.actions.push(action);}
 }
 
 impl State for ShapeWidgetState {
@@ -20,7 +30,21 @@ impl State for ShapeWidgetState {
     }
     fn update(&mut self, _: &mut Registry, ctx: &mut Context)
     {
-
+        let actions: Vec<ShapeWidgetAction> = self.actions.drain(..).collect();
+        for action in actions
+        {
+            match action
+            {
+                //The physic world updates the body only when asked, so that it does not check every widget at every update
+                ShapeWidgetAction::PhysicalPropertiesChanged=>
+                {
+                    if let PhysicWorldEntity(Some(physic_world)) = ctx.widget().clone::<PhysicWorldEntity>("physic_world")
+                    {
+                        ctx.get_widget(physic_world).get_mut::<PhysicWorldActions>("physic_world_actions").push(PhysicWorldAction::UpdateEntity(ctx.entity));
+                    }
+                }
+            }
+        }
     }
 }
 
@@ -30,11 +54,34 @@ widget!(
     When added to the Battlefield, it will detect that property and add to the physic engine.
     Using this widget is not mandatory.
     The Battlefield widget will accept as "physic widget" every widget that have physical_shape and physical_position properties.
+    The previous_physical_position and render_position properties are used to draw the shape between two physics steps,
+    while render_scale and render_rotation are set by the camera zoom and rotation.
+    The optional body_status property tells if the body is static, kinematic or dynamic (the default).
+    The optional density, friction, restitution, linear_damping and angular_damping properties define how the body behaves,
+    when a widget does not have them the default values are used.
+    The optional sleep_threshold property is the kinetic energy under which the body stops being simulated.
+    Fast bodies, as projectiles, should set the optional ccd_enabled property so that they do not pass through thin walls.
+    Changes of these properties are sent to the physic world set in the physic_world property when the widget is added.
     */
     ShapeWidget<ShapeWidgetState>
     {
         physical_shape: PhysicalShape,
         physical_position: IsometryF64,
+        previous_physical_position: IsometryF64,
+        render_position: IsometryF64,
+        render_scale: f64,
+        render_rotation: f64,
+        body_status: PhysicalBodyStatus,
+        collision_groups: PhysicalCollisionGroups,
+        density: f64,
+        friction: f64,
+        restitution: f64,
+        linear_damping: f64,
+        angular_damping: f64,
+        ccd_enabled: bool,
+        sleep_threshold: SleepThreshold,
+        ///Set when the widget is added to a physic world
+        physic_world: PhysicWorldEntity,
         background: Brush
     }
 );
@@ -42,6 +89,22 @@ widget!(
 impl Template for ShapeWidget {
     fn template(self, id: Entity, ctx: &mut BuildContext) -> Self {
         self.name("ShapeWidget")
+        .density(DEFAULT_DENSITY)
+        .friction(DEFAULT_FRICTION)
+        .restitution(DEFAULT_RESTITUTION)
+        .linear_damping(DEFAULT_LINEAR_DAMPING)
+        .angular_damping(DEFAULT_ANGULAR_DAMPING)
+        .render_scale(1.0)
+        .on_changed(|states, entity, property_name| {
+            match property_name
+            {
+                "body_status" | "density" | "friction" | "restitution" | "linear_damping" | "angular_damping" | "ccd_enabled" | "sleep_threshold"=>
+                {
+                    states.get_mut::<ShapeWidgetState>(entity).action(ShapeWidgetAction::PhysicalPropertiesChanged);
+                }
+                _=>{}
+            }
+        })
 
     }
 
@@ -52,6 +115,9 @@ impl Template for ShapeWidget {
 
 use std::f64::consts::PI;
 
+use nphysics2d::math::Isometry;
+use na::Point2;
+
 pub struct ShapeWidgetRenderObject;
 
 impl Into<Box<dyn RenderObject>> for ShapeWidgetRenderObject {
@@ -60,6 +126,22 @@ impl Into<Box<dyn RenderObject>> for ShapeWidgetRenderObject {
     }
 }
 
+//Where the shape is drawn: the local origin of the shape on the screen, the scale given by the camera zoom
+//and the rotation given by the camera rotation
+struct RenderFrame
+{
+    center: Point2<f64>,
+    scale: f64,
+    rotation: f64
+}
+
+impl RenderFrame
+{
+    fn x(&self, point: &Point2<f64>)->f64 {self.center.x + (point.x * self.rotation.cos() - point.y * self.rotation.sin()) * self.scale}
+    fn y(&self, point: &Point2<f64>)->f64 {self.center.y + (point.x * self.rotation.sin() + point.y * self.rotation.cos()) * self.scale}
+    fn angle(&self, angle: f64)->f64 {angle + self.rotation}
+}
+
 fn render_circle(
     render_context_2_d: &mut RenderContext2D,
     x: f64,
@@ -71,28 +153,147 @@ fn render_circle(
     render_context_2_d.arc(x + width / 2.0, y + height / 2.0, radius, 0., 2. * PI);
     render_context_2_d.close_path();
 }
+
+fn render_polygon(
+    render_context_2_d: &mut RenderContext2D,
+    frame: &RenderFrame,
+    isometry: &Isometry<f64>,
+    points: &[Point2<f64>],
+) {
+    render_context_2_d.begin_path();
+    for (index,point) in points.iter().enumerate()
+    {
+        let point = isometry * point;
+        if index == 0 {render_context_2_d.move_to(frame.x(&point), frame.y(&point));}
+        else {render_context_2_d.line_to(frame.x(&point), frame.y(&point));}
+    }
+    render_context_2_d.close_path();
+}
+
+fn render_capsule(
+    render_context_2_d: &mut RenderContext2D,
+    frame: &RenderFrame,
+    isometry: &Isometry<f64>,
+    half_height: f64,
+    radius: f64,
+) {
+    //The capsule axis is the local y axis, the two caps are drawn as half circles
+    let angle = frame.angle(isometry.rotation.angle());
+    let top = isometry * Point2::new(0.0,-half_height);
+    let bottom = isometry * Point2::new(0.0,half_height);
+
+    render_context_2_d.begin_path();
+    render_context_2_d.arc(frame.x(&top), frame.y(&top), radius * frame.scale, angle + PI, angle + 2. * PI);
+    render_context_2_d.arc(frame.x(&bottom), frame.y(&bottom), radius * frame.scale, angle, angle + PI);
+    render_context_2_d.close_path();
+}
+
+fn render_segment(
+    render_context_2_d: &mut RenderContext2D,
+    frame: &RenderFrame,
+    isometry: &Isometry<f64>,
+    a: &Point2<f64>,
+    b: &Point2<f64>,
+) {
+    let a = isometry * a;
+    let b = isometry * b;
+
+    render_context_2_d.begin_path();
+    render_context_2_d.move_to(frame.x(&a), frame.y(&a));
+    render_context_2_d.line_to(frame.x(&b), frame.y(&b));
+}
+
+fn render_shape(
+    render_context_2_d: &mut RenderContext2D,
+    frame: &RenderFrame,
+    isometry: &Isometry<f64>,
+    shape: &PhysicalShape,
+    background: &Brush,
+) {
+    match shape
+    {
+        PhysicalShape::Ball2D(ball)=>
+        {
+            let position = isometry * Point2::origin();
+            render_circle(
+                render_context_2_d,
+                frame.x(&position),
+                frame.y(&position),
+                0.0,
+                0.0,
+                ball.radius() * frame.scale,
+            );
+            render_context_2_d.set_fill_style(background.clone());
+            render_context_2_d.fill();
+        }
+        PhysicalShape::Cuboid2D(cuboid)=>
+        {
+            let half_extents = cuboid.half_extents();
+            let points = [
+                Point2::new(-half_extents.x,-half_extents.y),
+                Point2::new(half_extents.x,-half_extents.y),
+                Point2::new(half_extents.x,half_extents.y),
+                Point2::new(-half_extents.x,half_extents.y)
+            ];
+            render_polygon(render_context_2_d,frame,isometry,&points);
+            render_context_2_d.set_fill_style(background.clone());
+            render_context_2_d.fill();
+        }
+        PhysicalShape::ConvexPolygon2D(polygon)=>
+        {
+            render_polygon(render_context_2_d,frame,isometry,polygon.points());
+            render_context_2_d.set_fill_style(background.clone());
+            render_context_2_d.fill();
+        }
+        PhysicalShape::Capsule2D(capsule)=>
+        {
+            render_capsule(render_context_2_d,frame,isometry,capsule.half_height(),capsule.radius());
+            render_context_2_d.set_fill_style(background.clone());
+            render_context_2_d.fill();
+        }
+        PhysicalShape::Segment2D(segment)=>
+        {
+            //A segment has no area, so it is stroked instead of filled
+            render_segment(render_context_2_d,frame,isometry,segment.a(),segment.b());
+            render_context_2_d.set_stroke_style(background.clone());
+            render_context_2_d.stroke();
+        }
+        PhysicalShape::Compound2D(parts)=>
+        {
+            //Every part is drawn on its own, placed relative to the body
+            for (part_isometry,part_shape) in parts
+            {
+                render_shape(render_context_2_d,frame,&(isometry * part_isometry),part_shape,background);
+            }
+        }
+    }
+}
+
 impl RenderObject for ShapeWidgetRenderObject {
     fn render_self(&self, ctx: &mut Context, global_position: &Point) {
         let background = ctx.widget().clone::<Brush>("background");
         let bounds = ctx.widget().clone::<Rectangle>("bounds");
+        let physical_shape = ctx.widget().clone::<PhysicalShape>("physical_shape");
 
-        match ctx.widget().clone::<PhysicalShape>("physical_shape")
+        //Shapes are drawn around the center of the bounds, rotated as the physical body and transformed by the camera
+        let frame = RenderFrame
         {
-            PhysicalShape::Ball2D(ball)=>
-            {
-                let radius = ball.radius();
-                render_circle(
-                    ctx.render_context_2_d(),
-                    global_position.x() + bounds.x(),
-                    global_position.y() + bounds.y(),
-                    bounds.width(),
-                    bounds.height(),
-                    radius,
-                );
-                ctx.render_context_2_d().set_fill_style(background);
-                ctx.render_context_2_d().fill();
-            }
-            _=>panic!("Not implemented")
-        }
+            center: Point2::new(
+                global_position.x() + bounds.x() + bounds.width() / 2.0,
+                global_position.y() + bounds.y() + bounds.height() / 2.0
+            ),
+            scale: ctx.widget().try_clone::<f64>("render_scale").unwrap_or(1.0),
+            rotation: ctx.widget().try_clone::<f64>("render_rotation").unwrap_or(0.0)
+        };
+        //The pose set by the CameraLayout is preferred over the last simulated one
+        let position = ctx.widget().try_clone::<IsometryF64>("render_position")
+        .or_else(|| ctx.widget().try_clone::<IsometryF64>("physical_position"));
+        let isometry = match position
+        {
+            Some(physical_position)=>Isometry::rotation(physical_position.rotation.angle()),
+            None=>Isometry::identity()
+        };
+
+        render_shape(ctx.render_context_2_d(),&frame,&isometry,&physical_shape,&background);
     }
 }
@@ -0,0 +1,49 @@
+use orbtk::prelude::*;
+
+use super::PhysicalShape;
+use super::IsometryF64;
+use super::PhysicalCollisionGroups;
+
+#[derive(Default,AsAny)]
+pub struct TriggerAreaState {
+
+}
+
+impl TriggerAreaState
+{
+
+}
+
+impl State for TriggerAreaState {
+    fn init(&mut self, registry: &mut Registry, ctx: &mut Context)
+    {
+
+    }
+    fn update(&mut self, _: &mut Registry, ctx: &mut Context)
+    {
+
+    }
+}
+
+widget!(
+    /**
+    TriggerArea is an invisible area that detects the entities that enter or leave it, without colliding with them.
+    When added to the Battlefield, it will be registered on the physic engine as a sensor attached to a static body.
+    Every time an entity enters or leaves the area, an AreaEntered or AreaLeft event is sent to the layers with the area_name.
+    It can be used for capture zones, mines or spawn regions.
+    */
+    TriggerArea<TriggerAreaState>
+    {
+        physical_shape: PhysicalShape,
+        physical_position: IsometryF64,
+        collision_groups: PhysicalCollisionGroups,
+        area_name: String16
+    }
+);
+
+impl Template for TriggerArea {
+    fn template(self, id: Entity, ctx: &mut BuildContext) -> Self {
+        self.name("TriggerArea")
+
+    }
+}